@@ -0,0 +1,249 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+use crate::{legend::TileDef, level::LevelData, tileset::Tileset};
+
+//Neighbour bits, edges only
+const EDGE_NORTH: u8 = 1;
+const EDGE_EAST: u8 = 2;
+const EDGE_SOUTH: u8 = 4;
+const EDGE_WEST: u8 = 8;
+
+//Neighbour bits with corners, row by row from the top left
+const BLOB_NORTH_WEST: u8 = 1;
+const BLOB_NORTH: u8 = 2;
+const BLOB_NORTH_EAST: u8 = 4;
+const BLOB_WEST: u8 = 8;
+const BLOB_EAST: u8 = 16;
+const BLOB_SOUTH_WEST: u8 = 32;
+const BLOB_SOUTH: u8 = 64;
+const BLOB_SOUTH_EAST: u8 = 128;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum AutotileMode {
+    //Four edge neighbours, 16 variants
+    Edges16,
+    //Edges plus the corners between two connected edges, 47 variants
+    Blob47,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum LevelEdge {
+    North,
+    East,
+    South,
+    West,
+}
+
+//Named rule set of a tileset, picking a tile's atlas index from which of its neighbours it connects to.
+//Cells outside the level count as connected, except beyond the open edges
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct AutotileRules {
+    pub mode: AutotileMode,
+    //Atlas index of the lowest mask, the other masks following in ascending order
+    pub first_index: Option<usize>,
+    //Atlas index per mask, taking precedence over first_index
+    pub indices: HashMap<u8, usize>,
+    //Only the north edge by default, so the top row gets surface art instead of looking buried
+    pub open_edges: Vec<LevelEdge>,
+}
+
+//Legend side of autotiling: the rule set of the tile's tileset to use and which symbols count as neighbours
+#[derive(Debug, Clone, Deserialize)]
+pub struct TileAutotile {
+    pub rules: String,
+    //Other symbols the tile blends with, it always blends with itself
+    #[serde(default)]
+    pub connects: Vec<char>,
+}
+
+impl Default for AutotileRules {
+    fn default() -> Self {
+        AutotileRules {
+            mode: AutotileMode::Edges16,
+            first_index: None,
+            indices: HashMap::new(),
+            open_edges: vec![LevelEdge::North],
+        }
+    }
+}
+
+impl AutotileMode {
+    //Every mask the mode can produce, ascending
+    pub fn masks(self) -> Vec<u8> {
+        match self {
+            AutotileMode::Edges16 => (0..16).collect(),
+            AutotileMode::Blob47 => (0..=u8::MAX).filter(|mask| reduce_blob(*mask) == *mask).collect(),
+        }
+    }
+}
+
+impl AutotileRules {
+    pub fn mask(&self, level: &LevelData, x: usize, y: usize, connects: &[char]) -> u8 {
+        let symbol = level.get(x, y);
+        let connected = |dx: i32, dy: i32| {
+            let (nx, ny) = (x as i32 + dx, y as i32 + dy);
+            let beyond = [
+                (LevelEdge::North, ny < 0),
+                (LevelEdge::East, nx >= level.width as i32),
+                (LevelEdge::South, ny >= level.height as i32),
+                (LevelEdge::West, nx < 0),
+            ];
+            if beyond.iter().any(|(_, outside)| *outside) {
+                return !beyond.iter().any(|(edge, outside)| *outside && self.open_edges.contains(edge));
+            }
+            match level.get(nx as usize, ny as usize) {
+                Some(neighbour) => Some(neighbour) == symbol || connects.contains(&neighbour),
+                None => false,
+            }
+        };
+
+        match self.mode {
+            AutotileMode::Edges16 => {
+                let mut mask = 0;
+                for (dx, dy, bit) in [(0, -1, EDGE_NORTH), (1, 0, EDGE_EAST), (0, 1, EDGE_SOUTH), (-1, 0, EDGE_WEST)] {
+                    if connected(dx, dy) {
+                        mask |= bit;
+                    }
+                }
+                mask
+            }
+            AutotileMode::Blob47 => {
+                let mut mask = 0;
+                for (dx, dy, bit) in [
+                    (-1, -1, BLOB_NORTH_WEST),
+                    (0, -1, BLOB_NORTH),
+                    (1, -1, BLOB_NORTH_EAST),
+                    (-1, 0, BLOB_WEST),
+                    (1, 0, BLOB_EAST),
+                    (-1, 1, BLOB_SOUTH_WEST),
+                    (0, 1, BLOB_SOUTH),
+                    (1, 1, BLOB_SOUTH_EAST),
+                ] {
+                    if connected(dx, dy) {
+                        mask |= bit;
+                    }
+                }
+                reduce_blob(mask)
+            }
+        }
+    }
+
+    pub fn atlas_index(&self, mask: u8) -> Option<usize> {
+        if let Some(index) = self.indices.get(&mask) {
+            return Some(*index);
+        }
+        let first_index = self.first_index?;
+        self.mode
+            .masks()
+            .iter()
+            .position(|candidate| *candidate == mask)
+            .map(|position| first_index + position)
+    }
+}
+
+//A corner only matters when both edges next to it connect
+fn reduce_blob(mask: u8) -> u8 {
+    let mut reduced = mask;
+    for (corner, first, second) in [
+        (BLOB_NORTH_WEST, BLOB_NORTH, BLOB_WEST),
+        (BLOB_NORTH_EAST, BLOB_NORTH, BLOB_EAST),
+        (BLOB_SOUTH_WEST, BLOB_SOUTH, BLOB_WEST),
+        (BLOB_SOUTH_EAST, BLOB_SOUTH, BLOB_EAST),
+    ] {
+        if mask & first == 0 || mask & second == 0 {
+            reduced &= !corner;
+        }
+    }
+    reduced
+}
+
+//Atlas index for the tile at x, y, from the autotile rules it names in its tileset when it has any and
+//the masks they cover
+pub fn tile_atlas_index(def: &TileDef, tileset: Option<&Tileset>, level: &LevelData, x: usize, y: usize) -> Option<usize> {
+    def.autotile
+        .as_ref()
+        .and_then(|autotile| {
+            let rules = tileset?.autotile(&autotile.rules)?;
+            rules.atlas_index(rules.mask(level, x, y, &autotile.connects))
+        })
+        .or(def.atlas_index)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn level(rows: &[&str]) -> LevelData {
+        let grid = rows
+            .iter()
+            .map(|row| row.chars().map(|symbol| if symbol == ' ' { None } else { Some(symbol) }).collect())
+            .collect();
+        LevelData::from_grid(grid).unwrap()
+    }
+
+    fn rules(mode: AutotileMode) -> AutotileRules {
+        AutotileRules { mode, ..Default::default() }
+    }
+
+    #[test]
+    fn edge_masks_count_connected_neighbours_and_the_outside() {
+        let mut rules = rules(AutotileMode::Edges16);
+
+        let row = level(&["     ", " ##+ ", "     "]);
+        assert_eq!(rules.mask(&row, 1, 1, &[]), EDGE_EAST);
+        assert_eq!(rules.mask(&row, 2, 1, &[]), EDGE_WEST);
+        assert_eq!(rules.mask(&row, 2, 1, &['+']), EDGE_EAST | EDGE_WEST);
+
+        //Above the level is open by default
+        let corner = level(&["# ", "  "]);
+        assert_eq!(rules.mask(&corner, 0, 0, &[]), EDGE_WEST);
+        rules.open_edges = vec![];
+        assert_eq!(rules.mask(&corner, 0, 0, &[]), EDGE_NORTH | EDGE_WEST);
+        rules.open_edges = vec![LevelEdge::North, LevelEdge::West];
+        assert_eq!(rules.mask(&corner, 0, 0, &[]), 0);
+    }
+
+    #[test]
+    fn blob_masks_drop_corners_without_both_edges() {
+        let rules = rules(AutotileMode::Blob47);
+
+        let block = level(&["## ", "## ", "   "]);
+        assert_eq!(
+            rules.mask(&block, 0, 0, &[]),
+            BLOB_WEST | BLOB_EAST | BLOB_SOUTH_WEST | BLOB_SOUTH | BLOB_SOUTH_EAST
+        );
+        assert_eq!(rules.mask(&block, 1, 1, &[]), BLOB_NORTH_WEST | BLOB_NORTH | BLOB_WEST);
+
+        let diagonal = level(&["#  ", " # ", "   "]);
+        assert_eq!(rules.mask(&diagonal, 1, 1, &[]), 0);
+    }
+
+    #[test]
+    fn modes_produce_16_and_47_masks() {
+        assert_eq!(AutotileMode::Edges16.masks().len(), 16);
+
+        let blob = AutotileMode::Blob47.masks();
+        assert_eq!(blob.len(), 47);
+        assert_eq!((blob[0], blob[46]), (0, u8::MAX));
+    }
+
+    #[test]
+    fn atlas_index_prefers_explicit_indices() {
+        let mut rules = rules(AutotileMode::Blob47);
+        rules.first_index = Some(10);
+        rules.indices.insert(u8::MAX, 3);
+
+        assert_eq!(rules.atlas_index(0), Some(10));
+        assert_eq!(rules.atlas_index(BLOB_NORTH), Some(11));
+        assert_eq!(rules.atlas_index(u8::MAX), Some(3));
+        //A lone corner is never produced by a Blob47 mask
+        assert_eq!(rules.atlas_index(BLOB_NORTH_WEST), None);
+
+        let edges = AutotileRules { indices: HashMap::from([(5, 3)]), ..Default::default() };
+        assert_eq!(edges.atlas_index(5), Some(3));
+        assert_eq!(edges.atlas_index(4), None);
+    }
+}
@@ -0,0 +1,175 @@
+use bevy::{
+    prelude::*,
+    render::mesh::{Indices, PrimitiveTopology},
+    sprite::MaterialMesh2dBundle,
+};
+
+use crate::{level::TileRect, spriteloader::TILE_SPRITE_SIZE, TILE_SIZE};
+
+//Levels are streamed and static tiles baked in chunks of CHUNK_SIZE x CHUNK_SIZE cells
+pub const CHUNK_SIZE: usize = 32;
+
+//Cells beyond the camera view in which chunks are spawned, and past which they are despawned again
+pub const STREAM_IN_MARGIN: f32 = CHUNK_SIZE as f32 * 0.5;
+pub const STREAM_OUT_MARGIN: f32 = CHUNK_SIZE as f32 * 1.5;
+
+//World space bounds of a baked chunk, used for culling
+#[derive(Component)]
+pub struct TileChunk {
+    pub min: Vec2,
+    pub max: Vec2,
+}
+
+//A tile to bake: its world translation and index in the atlas
+pub struct ChunkQuad {
+    pub translation: Vec3,
+    pub index: usize,
+}
+
+//Inclusive range of chunk coordinates
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkRange {
+    pub min: (usize, usize),
+    pub max: (usize, usize),
+}
+
+pub fn chunk_coord(x: usize, y: usize) -> (usize, usize) {
+    (x / CHUNK_SIZE, y / CHUNK_SIZE)
+}
+
+pub fn chunk_bounds(coord: (usize, usize)) -> TileRect {
+    TileRect {
+        x: coord.0 * CHUNK_SIZE,
+        y: coord.1 * CHUNK_SIZE,
+        width: CHUNK_SIZE,
+        height: CHUNK_SIZE,
+    }
+}
+
+impl ChunkRange {
+    //Chunks of a width x height level overlapping the world space view, grown by margin cells
+    pub fn around(view_min: Vec2, view_max: Vec2, margin: f32, width: usize, height: usize) -> Option<ChunkRange> {
+        let left = view_min.x / TILE_SIZE - margin;
+        let right = view_max.x / TILE_SIZE + margin;
+        //Rows grow downwards while world y grows upwards
+        let top = -view_max.y / TILE_SIZE - margin;
+        let bottom = -view_min.y / TILE_SIZE + margin;
+
+        if width == 0 || height == 0 || right < 0.0 || bottom < 0.0 || left >= width as f32 || top >= height as f32 {
+            return None;
+        }
+
+        Some(ChunkRange {
+            min: chunk_coord(left.max(0.0) as usize, top.max(0.0) as usize),
+            max: chunk_coord((right as usize).min(width - 1), (bottom as usize).min(height - 1)),
+        })
+    }
+
+    //Chunks a rectangle of cells reaches into
+    pub fn covering(rect: TileRect) -> ChunkRange {
+        ChunkRange {
+            min: chunk_coord(rect.x, rect.y),
+            max: chunk_coord(rect.x + rect.width.max(1) - 1, rect.y + rect.height.max(1) - 1),
+        }
+    }
+
+    pub fn contains(&self, coord: (usize, usize)) -> bool {
+        coord.0 >= self.min.0 && coord.0 <= self.max.0 && coord.1 >= self.min.1 && coord.1 <= self.max.1
+    }
+
+    pub fn coords(&self) -> impl Iterator<Item = (usize, usize)> {
+        let (min, max) = (self.min, self.max);
+        (min.1..=max.1).flat_map(move |y| (min.0..=max.0).map(move |x| (x, y)))
+    }
+}
+
+//Builds a single mesh drawing every quad with the shared atlas material
+pub fn spawn_tile_chunk(
+    commands: &mut Commands,
+    meshes: &mut Assets<Mesh>,
+    atlas: &TextureAtlas,
+    material: Handle<ColorMaterial>,
+    quads: &[ChunkQuad]
+) -> Option<Entity> {
+    let origin = quads.first()?.translation;
+    let half = TILE_SPRITE_SIZE / 2.0;
+
+    let mut positions = Vec::with_capacity(quads.len() * 4);
+    let mut normals = Vec::with_capacity(quads.len() * 4);
+    let mut uvs = Vec::with_capacity(quads.len() * 4);
+    let mut indices = Vec::with_capacity(quads.len() * 6);
+    let mut min = Vec2::splat(f32::MAX);
+    let mut max = Vec2::splat(f32::MIN);
+
+    for quad in quads.iter() {
+        let rect = match atlas.textures.get(quad.index) {
+            Some(rect) => rect,
+            None => {
+                warn!("Atlas index {} is out of range", quad.index);
+                continue;
+            }
+        };
+        let uv_min = rect.min / atlas.size;
+        let uv_max = rect.max / atlas.size;
+        let center = (quad.translation - origin).truncate();
+        let base = positions.len() as u32;
+
+        positions.push([center.x - half, center.y - half, 0.0]);
+        positions.push([center.x + half, center.y - half, 0.0]);
+        positions.push([center.x + half, center.y + half, 0.0]);
+        positions.push([center.x - half, center.y + half, 0.0]);
+        normals.extend([[0.0, 0.0, 1.0]; 4]);
+        uvs.push([uv_min.x, uv_max.y]);
+        uvs.push([uv_max.x, uv_max.y]);
+        uvs.push([uv_max.x, uv_min.y]);
+        uvs.push([uv_min.x, uv_min.y]);
+        indices.extend([base, base + 1, base + 2, base, base + 2, base + 3]);
+
+        min = min.min(quad.translation.truncate() - Vec2::splat(half));
+        max = max.max(quad.translation.truncate() + Vec2::splat(half));
+    }
+
+    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
+    mesh.set_indices(Some(Indices::U32(indices)));
+
+    let chunk = commands
+        .spawn_bundle(MaterialMesh2dBundle {
+            mesh: meshes.add(mesh).into(),
+            material,
+            transform: Transform::from_translation(origin),
+            ..Default::default()
+        })
+        .insert(TileChunk { min, max })
+        .insert(Name::new("Tile chunk"))
+        .id();
+
+    Some(chunk)
+}
+
+//Hides chunks that are outside the camera view
+pub fn cull_chunks(
+    camera_query: Query<(&Transform, &OrthographicProjection), With<Camera>>,
+    mut chunk_query: Query<(&TileChunk, &mut Visibility)>
+) {
+    let (camera_transform, projection) = match camera_query.get_single() {
+        Ok(camera) => camera,
+        Err(_) => return,
+    };
+    let center = camera_transform.translation.truncate();
+    let view_min = center + Vec2::new(projection.left, projection.bottom);
+    let view_max = center + Vec2::new(projection.right, projection.top);
+
+    for (chunk, mut visibility) in chunk_query.iter_mut() {
+        let visible = chunk.max.x >= view_min.x
+            && chunk.min.x <= view_max.x
+            && chunk.max.y >= view_min.y
+            && chunk.min.y <= view_max.y;
+
+        if visibility.is_visible != visible {
+            visibility.is_visible = visible;
+        }
+    }
+}
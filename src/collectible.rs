@@ -0,0 +1,147 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+
+use crate::{player::{wall_collision_check, Player}, tilemap::{LoadedLevel, StreamedChunks, TileCell}, legend::TileMarker, GameState};
+
+pub struct CollectiblePlugin;
+
+//Coins, gems and anything else that is picked up by walking into it
+#[derive(Component)]
+pub struct Collectible {
+    pub value: u32,
+}
+
+//Collectibles of one level, counted again every time the level is spawned
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LevelScore {
+    pub collected: usize,
+    pub available: usize,
+    pub points: u32,
+}
+
+//Keyed by the level's index in the LevelRegistry
+#[derive(Default)]
+pub struct Score {
+    pub levels: HashMap<usize, LevelScore>,
+}
+
+//Sent for every pickup so audio and the HUD can react
+pub struct CollectiblePicked {
+    pub level: usize,
+    pub value: u32,
+    pub collected: usize,
+    pub available: usize,
+}
+
+impl Score {
+    pub fn points(&self) -> u32 {
+        self.levels.values().map(|level| level.points).sum()
+    }
+
+    pub fn level(&self, index: usize) -> LevelScore {
+        self.levels.get(&index).copied().unwrap_or_default()
+    }
+}
+
+impl Plugin for CollectiblePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Score>()
+        .add_event::<CollectiblePicked>()
+        .add_system(count_collectibles)
+        .add_system_set(
+            SystemSet::on_update(GameState::Overworld)
+                .with_system(pick_up_collectibles.label("pick_up_collectibles"))
+                .with_system(report_pickups.after("pick_up_collectibles"))
+        );
+    }
+}
+
+//Starts the score of a level over whenever it is spawned or rebuilt
+fn count_collectibles(
+    loaded_level: Option<Res<LoadedLevel>>,
+    mut score: ResMut<Score>
+) {
+    let loaded_level = match loaded_level {
+        Some(loaded_level) if loaded_level.is_changed() => loaded_level,
+        _ => return,
+    };
+
+    let legend = &loaded_level.legend;
+    let is_collectible = |symbol: char| {
+        legend
+            .get(symbol)
+            .is_some_and(|def| def.markers.contains(&TileMarker::Collectible))
+    };
+
+    //Collectibles hidden in destructible tiles count as well
+    let available = loaded_level
+        .level
+        .tiles()
+        .filter(|tile_data| {
+            is_collectible(tile_data.symbol)
+                || legend
+                    .get(tile_data.symbol)
+                    .and_then(|def| def.drop)
+                    .is_some_and(is_collectible)
+        })
+        .count();
+
+    score.levels.insert(loaded_level.index, LevelScore { available, ..Default::default() });
+}
+
+//Picks up collectibles the player overlaps, they stay gone when their chunk is streamed back in
+fn pick_up_collectibles(
+    mut commands: Commands,
+    loaded_level: Option<Res<LoadedLevel>>,
+    mut score: ResMut<Score>,
+    streamed: Option<ResMut<StreamedChunks>>,
+    player_query: Query<&Transform, With<Player>>,
+    collectible_query: Query<(Entity, &Transform, &Collectible, &TileCell), Without<Player>>,
+    mut picked_events: EventWriter<CollectiblePicked>
+) {
+    let (loaded_level, mut streamed) = match (loaded_level, streamed) {
+        (Some(loaded_level), Some(streamed)) => (loaded_level, streamed),
+        _ => return,
+    };
+    let player_translation = match player_query.get_single() {
+        Ok(transform) => transform.translation,
+        Err(_) => return,
+    };
+
+    for (entity, transform, collectible, cell) in collectible_query.iter() {
+        if !wall_collision_check(player_translation, transform.translation) {
+            continue;
+        }
+
+        commands.entity(entity).despawn_recursive();
+        streamed.clear_cell(cell.x, cell.y);
+
+        //Keyed by the level the collectible was in, the goal may already have requested the next one
+        let level_score = score.levels.entry(loaded_level.index).or_default();
+        level_score.collected += 1;
+        level_score.points += collectible.value;
+
+        picked_events.send(CollectiblePicked {
+            level: loaded_level.index,
+            value: collectible.value,
+            collected: level_score.collected,
+            available: level_score.available,
+        });
+    }
+}
+
+//Logs every pickup with the score so far
+fn report_pickups(score: Res<Score>, mut picked_events: EventReader<CollectiblePicked>) {
+    for picked in picked_events.iter() {
+        info!(
+            "Collected {} of {} in stage {} for {} points, {} in the stage and {} in total",
+            picked.collected,
+            picked.available,
+            picked.level + 1,
+            picked.value,
+            score.level(picked.level).points,
+            score.points()
+        );
+    }
+}
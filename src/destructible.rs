@@ -0,0 +1,151 @@
+use std::f32::consts::PI;
+
+use bevy::{prelude::*, sprite::collide_aabb::collide};
+
+use crate::{player::{MeleeStrike, PlayerBullet}, tilemap::TileCell, TILE_SIZE, GameState};
+
+pub struct DestructiblePlugin;
+
+const BULLET_DAMAGE: u32 = 1;
+const MELEE_DAMAGE: u32 = 2;
+//How far in front of the player a melee swing hits
+const MELEE_REACH: f32 = TILE_SIZE * 1.5;
+
+const DEBRIS_COUNT: usize = 6;
+const DEBRIS_SECONDS: f32 = 0.6;
+const DEBRIS_SPEED: f32 = 0.8;
+const DEBRIS_GRAVITY: f32 = 3.0;
+
+//Tiles that break after taking enough hits, leaving their drop behind if they have one
+#[derive(Component)]
+pub struct Destructible {
+    pub hit_points: u32,
+    pub drop: Option<char>,
+}
+
+//Bits flying off a broken tile
+#[derive(Component)]
+struct Debris {
+    velocity: Vec2,
+    timer: Timer,
+}
+
+//The tilemap replaces the broken tile's cell with its drop, or empties it
+pub struct TileBroken {
+    pub x: usize,
+    pub y: usize,
+    pub drop: Option<char>,
+}
+
+impl Plugin for DestructiblePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<TileBroken>()
+        .add_system_set(
+            SystemSet::on_update(GameState::Overworld)
+                .with_system(bullet_hits.label("hit_tiles"))
+                .with_system(melee_hits.label("hit_tiles"))
+                .with_system(break_tiles.after("hit_tiles"))
+                .with_system(animate_debris)
+        );
+    }
+}
+
+//Bullets stop at the first destructible tile they fly into
+fn bullet_hits(
+    mut commands: Commands,
+    bullet_query: Query<(Entity, &Transform), With<PlayerBullet>>,
+    mut tile_query: Query<(&Transform, &mut Destructible), Without<PlayerBullet>>
+) {
+    for (bullet, bullet_transform) in bullet_query.iter() {
+        let hit = tile_query.iter_mut().find(|(transform, _)| {
+            collide(
+                bullet_transform.translation,
+                Vec2::splat(TILE_SIZE * 0.2),
+                transform.translation,
+                Vec2::splat(TILE_SIZE)
+            ).is_some()
+        });
+
+        if let Some((_, mut destructible)) = hit {
+            destructible.hit_points = destructible.hit_points.saturating_sub(BULLET_DAMAGE);
+            commands.entity(bullet).despawn_recursive();
+        }
+    }
+}
+
+//A swing hits every destructible tile within reach on the side the player faces
+fn melee_hits(
+    mut strike_events: EventReader<MeleeStrike>,
+    mut tile_query: Query<(&Transform, &mut Destructible)>
+) {
+    for strike in strike_events.iter() {
+        let direction = if strike.facing_right { 1.0 } else { -1.0 };
+        let reach_center = strike.origin + Vec3::new(direction * MELEE_REACH / 2.0, 0.0, 0.0);
+
+        for (transform, mut destructible) in tile_query.iter_mut() {
+            let hit = collide(
+                reach_center,
+                Vec2::new(MELEE_REACH, TILE_SIZE),
+                transform.translation,
+                Vec2::splat(TILE_SIZE)
+            ).is_some();
+
+            if hit {
+                destructible.hit_points = destructible.hit_points.saturating_sub(MELEE_DAMAGE);
+            }
+        }
+    }
+}
+
+//Removes tiles that are out of hit points, sprite and collider alike, and scatters some debris
+fn break_tiles(
+    mut commands: Commands,
+    tile_query: Query<(Entity, &Destructible, &Transform, &TileCell)>,
+    mut broken_events: EventWriter<TileBroken>
+) {
+    for (entity, destructible, transform, cell) in tile_query.iter() {
+        if destructible.hit_points > 0 {
+            continue;
+        }
+        commands.entity(entity).despawn_recursive();
+
+        for i in 0..DEBRIS_COUNT {
+            //Fanned out over the upper half
+            let angle = PI * (i as f32 + 0.5) / DEBRIS_COUNT as f32;
+            commands
+                .spawn_bundle(SpriteBundle {
+                    sprite: Sprite {
+                        color: Color::rgb(0.5, 0.5, 0.5),
+                        custom_size: Some(Vec2::splat(TILE_SIZE * 0.3)),
+                        ..Default::default()
+                    },
+                    transform: Transform::from_translation(transform.translation),
+                    ..Default::default()
+                })
+                .insert(Debris {
+                    velocity: Vec2::new(angle.cos(), angle.sin()) * DEBRIS_SPEED,
+                    timer: Timer::from_seconds(DEBRIS_SECONDS, false),
+                });
+        }
+
+        broken_events.send(TileBroken { x: cell.x, y: cell.y, drop: destructible.drop });
+    }
+}
+
+fn animate_debris(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut debris_query: Query<(Entity, &mut Debris, &mut Transform, &mut Sprite)>
+) {
+    for (entity, mut debris, mut transform, mut sprite) in debris_query.iter_mut() {
+        debris.timer.tick(time.delta());
+        if debris.timer.finished() {
+            commands.entity(entity).despawn_recursive();
+            continue;
+        }
+
+        debris.velocity.y -= DEBRIS_GRAVITY * time.delta_seconds();
+        transform.translation += debris.velocity.extend(0.0) * time.delta_seconds();
+        sprite.color.set_a(1.0 - debris.timer.percent());
+    }
+}
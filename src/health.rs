@@ -0,0 +1,273 @@
+use bevy::{prelude::*, math::const_vec2};
+use bevy_rapier2d::prelude::*;
+
+use crate::{player::{wall_collision_check, DroppingThrough, Player, PLAYER_GRAVITY_SCALE}, tilemap::{ActiveCheckpoint, LevelStart, LoadedLevel}, TILE_SIZE, GameState};
+
+pub struct HealthPlugin;
+
+pub const PLAYER_MAX_HEALTH: u32 = 3;
+
+//Cells below the bottom row of the level at which a fall counts as death
+const KILL_PLANE_DEPTH: f32 = 5.0;
+
+const INVULNERABLE_SECONDS: f32 = 1.0;
+//The player is shown for the first half of every blink and faded for the second
+const BLINK_SECONDS: f32 = 0.2;
+const KNOCKBACK_SECONDS: f32 = 0.25;
+const KNOCKBACK_VELOCITY: Vec2 = const_vec2!([1.5, 1.5]);
+
+#[derive(Component)]
+pub struct Health {
+    pub current: u32,
+    pub max: u32,
+}
+
+//Tiles that hurt the player on touch
+#[derive(Component)]
+pub struct Hazard {
+    pub damage: u32,
+}
+
+//Hazards are ignored while this is on
+#[derive(Component)]
+pub struct Invulnerable {
+    timer: Timer,
+}
+
+//Player input is ignored while being knocked back
+#[derive(Component)]
+pub struct Knockback {
+    timer: Timer,
+}
+
+pub struct PlayerDamaged {
+    pub amount: u32,
+    pub remaining: u32,
+}
+
+pub struct PlayerDied;
+
+type VulnerablePlayer<'a> = (Entity, &'a Transform, &'a mut Health, &'a mut Velocity);
+
+impl Health {
+    pub fn new(max: u32) -> Self {
+        Health { current: max, max }
+    }
+}
+
+impl Plugin for HealthPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<PlayerDamaged>()
+        .add_event::<PlayerDied>()
+        .add_system_set(
+            SystemSet::on_update(GameState::Overworld)
+                .with_system(touch_hazards.label("damage_player"))
+                .with_system(fall_out_of_level.label("damage_player"))
+                .with_system(respawn_player.after("damage_player"))
+                .with_system(tick_invulnerability)
+                .with_system(report_damage.after("damage_player"))
+        );
+    }
+}
+
+//Hurts and knocks back the player when touching a hazard
+fn touch_hazards(
+    mut commands: Commands,
+    mut player_query: Query<VulnerablePlayer, (With<Player>, Without<Invulnerable>)>,
+    hazard_query: Query<(&Transform, &Hazard), Without<Player>>,
+    mut damaged_events: EventWriter<PlayerDamaged>,
+    mut died_events: EventWriter<PlayerDied>
+) {
+    let (player, transform, mut health, mut velocity) = match player_query.get_single_mut() {
+        Ok(player) => player,
+        Err(_) => return,
+    };
+
+    let touched = hazard_query
+        .iter()
+        .find(|(hazard_transform, _)| wall_collision_check(transform.translation, hazard_transform.translation));
+
+    let (hazard_transform, hazard) = match touched {
+        Some(touched) => touched,
+        None => return,
+    };
+
+    health.current = health.current.saturating_sub(hazard.damage);
+    damaged_events.send(PlayerDamaged { amount: hazard.damage, remaining: health.current });
+
+    if health.current == 0 {
+        died_events.send(PlayerDied);
+        return;
+    }
+
+    //Away from the hazard and up
+    let direction = if transform.translation.x < hazard_transform.translation.x { -1.0 } else { 1.0 };
+    velocity.linvel = Vec2::new(KNOCKBACK_VELOCITY.x * direction, KNOCKBACK_VELOCITY.y);
+
+    commands.entity(player)
+        .insert(Invulnerable {
+            timer: Timer::from_seconds(INVULNERABLE_SECONDS, false)
+        })
+        .insert(Knockback {
+            timer: Timer::from_seconds(KNOCKBACK_SECONDS, false)
+        });
+}
+
+//Falling into a pit below the level kills the player
+fn fall_out_of_level(
+    loaded_level: Option<Res<LoadedLevel>>,
+    player_query: Query<&Transform, With<Player>>,
+    mut died_events: EventWriter<PlayerDied>
+) {
+    let loaded_level = match loaded_level {
+        Some(loaded_level) => loaded_level,
+        None => return,
+    };
+    let kill_plane = kill_plane(loaded_level.level.height);
+
+    for transform in player_query.iter() {
+        if transform.translation.y < kill_plane {
+            died_events.send(PlayerDied);
+        }
+    }
+}
+
+//Height below which the player has fallen out of a level with the given number of rows
+fn kill_plane(rows: usize) -> f32 {
+    -(rows as f32 + KILL_PLANE_DEPTH) * TILE_SIZE
+}
+
+fn report_damage(mut damaged_events: EventReader<PlayerDamaged>) {
+    for damaged in damaged_events.iter() {
+        info!("Player took {} damage, {} health left", damaged.amount, damaged.remaining);
+    }
+}
+
+//Puts the player back at the last checkpoint, or the start of the level, with full health
+fn respawn_player(
+    mut commands: Commands,
+    level_start: Option<Res<LevelStart>>,
+    active_checkpoint: Res<ActiveCheckpoint>,
+    mut died_events: EventReader<PlayerDied>,
+    mut player_query: Query<(Entity, &mut Player, &mut Transform, &mut Velocity, &mut GravityScale, &mut Health)>
+) {
+    if died_events.iter().count() == 0 {
+        return;
+    }
+    let respawn = match (active_checkpoint.0, level_start) {
+        (Some(checkpoint), _) => checkpoint,
+        (None, Some(level_start)) => level_start.0,
+        (None, None) => return,
+    };
+    info!("Player died");
+
+    for (entity, mut player, mut transform, mut velocity, mut gravity_scale, mut health) in player_query.iter_mut() {
+        transform.translation = respawn;
+        velocity.linvel = Vec2::new(0.0, 0.0);
+        velocity.angvel = 0.0;
+        gravity_scale.0 = PLAYER_GRAVITY_SCALE;
+        health.current = health.max;
+        player.reset();
+
+        commands.entity(entity)
+            .remove::<Knockback>()
+            .remove::<DroppingThrough>()
+            .insert(Invulnerable {
+                timer: Timer::from_seconds(INVULNERABLE_SECONDS, false)
+            });
+    }
+}
+
+//Counts down invulnerability and knockback, blinking the player meanwhile
+fn tick_invulnerability(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut invulnerable_query: Query<(Entity, &mut Invulnerable, &mut TextureAtlasSprite)>,
+    mut knockback_query: Query<(Entity, &mut Knockback)>
+) {
+    for (entity, mut invulnerable, mut sprite) in invulnerable_query.iter_mut() {
+        invulnerable.timer.tick(time.delta());
+
+        if invulnerable.timer.finished() {
+            sprite.color.set_a(1.0);
+            commands.entity(entity).remove::<Invulnerable>();
+        } else {
+            let visible = (invulnerable.timer.elapsed_secs() / BLINK_SECONDS).fract() < 0.5;
+            sprite.color.set_a(if visible { 1.0 } else { 0.3 });
+        }
+    }
+
+    for (entity, mut knockback) in knockback_query.iter_mut() {
+        knockback.timer.tick(time.delta());
+
+        if knockback.timer.finished() {
+            commands.entity(entity).remove::<Knockback>();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::event::Events;
+
+    use super::*;
+
+    fn world_with_player(health: u32) -> (World, Entity) {
+        let mut world = World::new();
+        world.init_resource::<Events<PlayerDamaged>>();
+        world.init_resource::<Events<PlayerDied>>();
+        let player = world
+            .spawn()
+            .insert(Player::default())
+            .insert(Transform::default())
+            .insert(Health { current: health, max: PLAYER_MAX_HEALTH })
+            .insert(Velocity::default())
+            .id();
+        (world, player)
+    }
+
+    fn touch(world: &mut World) {
+        let mut stage = SystemStage::single(touch_hazards);
+        stage.run(world);
+    }
+
+    #[test]
+    fn kill_plane_lies_below_the_bottom_row() {
+        assert_eq!(kill_plane(0), -KILL_PLANE_DEPTH * TILE_SIZE);
+        assert_eq!(kill_plane(10), -15.0 * TILE_SIZE);
+        assert!(kill_plane(10) < kill_plane(9));
+    }
+
+    #[test]
+    fn hazards_hurt_once_until_invulnerability_runs_out() {
+        let (mut world, player) = world_with_player(PLAYER_MAX_HEALTH);
+        world.spawn().insert(Transform::default()).insert(Hazard { damage: 2 });
+
+        touch(&mut world);
+        assert_eq!(world.get::<Health>(player).unwrap().current, PLAYER_MAX_HEALTH - 2);
+        assert!(world.get::<Invulnerable>(player).is_some());
+        assert!(world.get::<Knockback>(player).is_some());
+        assert!(world.get::<Velocity>(player).unwrap().linvel.y > 0.0);
+        let damaged = world.resource::<Events<PlayerDamaged>>().iter_current_update_events().next().unwrap();
+        assert_eq!((damaged.amount, damaged.remaining), (2, PLAYER_MAX_HEALTH - 2));
+
+        //Still standing in the hazard
+        touch(&mut world);
+        assert_eq!(world.get::<Health>(player).unwrap().current, PLAYER_MAX_HEALTH - 2);
+
+        world.entity_mut(player).remove::<Invulnerable>();
+        touch(&mut world);
+        assert_eq!(world.get::<Health>(player).unwrap().current, 0);
+    }
+
+    #[test]
+    fn the_last_hit_kills_without_knockback() {
+        let (mut world, player) = world_with_player(1);
+        world.spawn().insert(Transform::default()).insert(Hazard { damage: 3 });
+
+        touch(&mut world);
+        assert_eq!(world.get::<Health>(player).unwrap().current, 0);
+        assert_eq!(world.resource::<Events<PlayerDied>>().iter_current_update_events().count(), 1);
+        assert!(world.get::<Invulnerable>(player).is_none());
+    }
+}
@@ -0,0 +1,343 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::{
+    legend::{TileLegend, TileMarker},
+    level::{LevelData, LevelError},
+};
+
+//Entity identifier that marks where the player begins
+const PLAYER_START: &str = "PlayerStart";
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LdtkProject {
+    defs: LdtkDefs,
+    #[serde(default)]
+    levels: Vec<LdtkLevel>,
+}
+
+#[derive(Deserialize)]
+struct LdtkDefs {
+    #[serde(default)]
+    layers: Vec<LdtkLayerDef>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LdtkLayerDef {
+    uid: i64,
+    #[serde(default)]
+    int_grid_values: Vec<LdtkIntGridValue>,
+}
+
+#[derive(Deserialize)]
+struct LdtkIntGridValue {
+    value: i64,
+    #[serde(default)]
+    identifier: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LdtkLevel {
+    identifier: String,
+    #[serde(default)]
+    layer_instances: Option<Vec<LdtkLayer>>,
+    //Set when the project is saved with "separate level files"
+    #[serde(default)]
+    external_rel_path: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LdtkLayer {
+    #[serde(rename = "__identifier")]
+    identifier: String,
+    #[serde(rename = "__type")]
+    kind: String,
+    #[serde(rename = "__cWid")]
+    columns: usize,
+    #[serde(rename = "__cHei")]
+    rows: usize,
+    #[serde(rename = "__gridSize")]
+    grid_size: f32,
+    layer_def_uid: i64,
+    #[serde(default)]
+    int_grid_csv: Vec<i64>,
+    #[serde(default)]
+    entity_instances: Vec<LdtkEntity>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LdtkEntity {
+    #[serde(rename = "__identifier")]
+    identifier: String,
+    //Where the pivot sits in the level, in pixels
+    px: [f32; 2],
+    #[serde(rename = "__pivot")]
+    pivot: [f32; 2],
+    #[serde(default)]
+    width: f32,
+    #[serde(default)]
+    height: f32,
+    #[serde(default)]
+    field_instances: Vec<LdtkField>,
+}
+
+#[derive(Deserialize)]
+struct LdtkField {
+    #[serde(rename = "__identifier")]
+    identifier: String,
+    #[serde(rename = "__value")]
+    value: Value,
+}
+
+//Reads every level of an LDtk project, keyed by identifier in project order. IntGrid values are matched to legend
+//entries by name, entities by a "symbol" field, their identifier as a legend name or marker, or PlayerStart.
+//Entities can carry a "path" for moving platforms and a "channel" linking switches to what they operate.
+//Levels saved as separate files have to be passed in by their relative path, see external_files.
+pub fn parse(
+    source: &str,
+    legend: &TileLegend,
+    external: &HashMap<String, String>
+) -> Result<Vec<(String, LevelData)>, LevelError> {
+    let project: LdtkProject = serde_json::from_str(source).map_err(|err| import_error(err.to_string()))?;
+
+    project
+        .levels
+        .iter()
+        .map(|level| Ok((level.identifier.clone(), parse_level(&project, level, legend, external)?)))
+        .collect()
+}
+
+//Relative paths of the levels a project keeps in separate files
+pub fn external_files(source: &str) -> Result<Vec<String>, LevelError> {
+    let project: LdtkProject = serde_json::from_str(source).map_err(|err| import_error(err.to_string()))?;
+
+    Ok(project
+        .levels
+        .into_iter()
+        .filter(|level| level.layer_instances.is_none())
+        .filter_map(|level| level.external_rel_path)
+        .collect())
+}
+
+fn parse_level(
+    project: &LdtkProject,
+    level: &LdtkLevel,
+    legend: &TileLegend,
+    external: &HashMap<String, String>
+) -> Result<LevelData, LevelError> {
+    let external_level;
+    let layers = match (&level.layer_instances, &level.external_rel_path) {
+        (Some(layers), _) => layers,
+        (None, Some(relative_path)) => {
+            let source = external
+                .get(relative_path)
+                .ok_or_else(|| LevelError::Io(format!("level file {} was not loaded", relative_path)))?;
+            external_level = serde_json::from_str::<LdtkLevel>(source).map_err(|err| import_error(err.to_string()))?;
+            external_level.layer_instances.as_ref().ok_or_else(|| import_error(format!("level '{}' has no layers", level.identifier)))?
+        }
+        (None, None) => return Err(import_error(format!("level '{}' has no layers", level.identifier))),
+    };
+
+    let value_names: HashMap<(i64, i64), &str> = project
+        .defs
+        .layers
+        .iter()
+        .flat_map(|layer| {
+            layer.int_grid_values.iter().filter_map(move |value| {
+                value.identifier.as_deref().map(|identifier| ((layer.uid, value.value), identifier))
+            })
+        })
+        .collect();
+
+    let columns = layers.iter().map(|layer| layer.columns).max().unwrap_or(0);
+    let rows = layers.iter().map(|layer| layer.rows).max().unwrap_or(0);
+    let mut grid = vec![vec![None; columns]; rows];
+    let mut player_starts = Vec::new();
+    let mut platform_paths = HashMap::new();
+    let mut channels = HashMap::new();
+
+    //LDtk lists the top layer first, so walk backwards to let upper layers win
+    for layer in layers.iter().rev() {
+        match layer.kind.as_str() {
+            "IntGrid" => {
+                if layer.int_grid_csv.len() != layer.columns * layer.rows {
+                    return Err(import_error(format!(
+                        "IntGrid layer '{}' has {} values for a {}x{} grid",
+                        layer.identifier,
+                        layer.int_grid_csv.len(),
+                        layer.columns,
+                        layer.rows
+                    )));
+                }
+                for (i, value) in layer.int_grid_csv.iter().enumerate() {
+                    if *value == 0 {
+                        continue;
+                    }
+                    let name = value_names.get(&(layer.layer_def_uid, *value)).ok_or_else(|| {
+                        import_error(format!("IntGrid value {} in layer '{}' has no identifier", value, layer.identifier))
+                    })?;
+                    let symbol = legend.symbol_named(name).ok_or_else(|| {
+                        import_error(format!("IntGrid value '{}' in layer '{}' is not in the legend", name, layer.identifier))
+                    })?;
+                    grid[i / layer.columns][i % layer.columns] = Some(symbol);
+                }
+            }
+            "Entities" => {
+                for entity in layer.entity_instances.iter() {
+                    let (x, y) = entity.top_left_cell(layer.grid_size);
+                    if y >= rows || x >= columns {
+                        continue;
+                    }
+
+                    if entity.identifier == PLAYER_START {
+                        player_starts.push((x, y));
+                        continue;
+                    }
+
+                    let symbol = entity_symbol(legend, entity)?;
+                    let cells = ((entity.width / layer.grid_size).round() as usize).max(1);
+                    let channel = entity.field("channel").and_then(|channel| channel.as_u64());
+                    for (column, cell) in grid[y].iter_mut().enumerate().skip(x).take(cells) {
+                        *cell = Some(symbol);
+                        if let Some(channel) = channel {
+                            channels.insert((column, y), channel as u32);
+                        }
+                    }
+
+                    if let Some(path) = entity.field("path") {
+                        platform_paths.insert((x, y), path_points(path));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mut level = LevelData::from_grid(grid)?;
+    level.platform_paths = platform_paths;
+    level.channels = channels;
+    for (x, y) in player_starts {
+        level.set_player_start(x, y)?;
+    }
+    level.take_player_start(legend)?;
+    Ok(level)
+}
+
+impl LdtkEntity {
+    //__grid is the cell under the pivot, so a centered or bottom pivot would shift the entity. Its cells are
+    //counted from the top left corner instead
+    fn top_left_cell(&self, grid_size: f32) -> (usize, usize) {
+        let left = self.px[0] - self.pivot[0] * self.width;
+        let top = self.px[1] - self.pivot[1] * self.height;
+        ((left / grid_size).floor().max(0.0) as usize, (top / grid_size).floor().max(0.0) as usize)
+    }
+
+    fn field(&self, identifier: &str) -> Option<&Value> {
+        self.field_instances
+            .iter()
+            .find(|field| field.identifier == identifier)
+            .map(|field| &field.value)
+    }
+}
+
+fn entity_symbol(legend: &TileLegend, entity: &LdtkEntity) -> Result<char, LevelError> {
+    if let Some(Value::String(symbol)) = entity.field("symbol") {
+        return symbol
+            .chars()
+            .next()
+            .filter(|symbol| legend.get(*symbol).is_some())
+            .ok_or_else(|| import_error(format!("symbol '{}' is not in the legend", symbol)));
+    }
+
+    if let Some(symbol) = legend.symbol_named(&entity.identifier) {
+        return Ok(symbol);
+    }
+
+    ron::from_str::<TileMarker>(&entity.identifier)
+        .ok()
+        .and_then(|marker| legend.symbol_with_marker(marker))
+        .ok_or_else(|| import_error(format!("entity '{}' does not match a legend entry", entity.identifier)))
+}
+
+//Accepts both Point and Array<Point> fields
+fn path_points(value: &Value) -> Vec<(usize, usize)> {
+    let point = |value: &Value| -> Option<(usize, usize)> {
+        Some((value.get("cx")?.as_u64()? as usize, value.get("cy")?.as_u64()? as usize))
+    };
+
+    match value {
+        Value::Array(points) => points.iter().filter_map(point).collect(),
+        value => point(value).into_iter().collect(),
+    }
+}
+
+fn import_error(message: String) -> LevelError {
+    LevelError::Import(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn caves() -> (TileLegend, LevelData) {
+        let legend = TileLegend::parse(include_str!("../assets/default.legend.ron")).unwrap();
+        let mut levels = parse(include_str!("../assets/caves.ldtk"), &legend, &HashMap::new()).unwrap();
+        assert_eq!(levels.len(), 1);
+        let (identifier, level) = levels.remove(0);
+        assert_eq!(identifier, "Caves");
+        (legend, level)
+    }
+
+    #[test]
+    fn maps_int_grid_values_to_symbols_by_name() {
+        let (_, level) = caves();
+
+        assert_eq!((level.width, level.height), (16, 8));
+        assert_eq!(level.get(0, 0), Some('|'));
+        assert_eq!(level.get(15, 6), Some('|'));
+        assert_eq!(level.get(7, 7), Some('#'));
+        assert_eq!(level.get(7, 2), None);
+    }
+
+    #[test]
+    fn places_entities_from_their_top_left_corner() {
+        let (legend, level) = caves();
+
+        //Bottom pivots put __grid a row too low, centered ones half the width too far right
+        assert_eq!(level.player_start, Some((2, 6)));
+        assert_eq!((level.get(5, 4), level.get(6, 4), level.get(7, 4)), (Some('-'), Some('-'), None));
+        assert_eq!(level.get(11, 6), Some('G'));
+        assert_eq!(level.get(4, 6), legend.symbol_named("lever"));
+        //Matched through its "symbol" field
+        assert_eq!(level.get(13, 6), Some('>'));
+    }
+
+    #[test]
+    fn reads_paths_and_channels() {
+        let (_, level) = caves();
+
+        assert_eq!(level.platform_paths.get(&(5, 4)), Some(&vec![(9, 4)]));
+        assert_eq!(level.channels.get(&(4, 6)), Some(&3));
+        assert_eq!(level.channels.get(&(11, 6)), Some(&3));
+        assert_eq!(level.channels.len(), 2);
+    }
+
+    #[test]
+    fn path_points_accept_points_and_arrays() {
+        assert_eq!(path_points(&json!({ "cx": 3, "cy": 4 })), vec![(3, 4)]);
+        assert_eq!(
+            path_points(&json!([{ "cx": 1, "cy": 2 }, { "cx": -1 }, { "cx": 5, "cy": 6 }])),
+            vec![(1, 2), (5, 6)]
+        );
+        assert!(path_points(&json!(null)).is_empty());
+    }
+}
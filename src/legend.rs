@@ -0,0 +1,589 @@
+use std::{collections::HashMap, fmt, path::{Path, PathBuf}};
+
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+use serde::Deserialize;
+
+use crate::{autotile::TileAutotile, tileset::DEFAULT_TILESET, collectible::Collectible, destructible::Destructible, health::Hazard, tilemap::{BouncePad, Checkpoint, Conveyor, Crumbling, Door, TimedBlock, EncounterSpawner, Switch, SwitchKind, SwitchTarget, Ladder, LevelGoal, MovingCollider, OneWayPlatform, Slope, TileCollider, WallCollider}};
+
+pub const DEFAULT_LEGEND: &str = "default.legend.ron";
+
+//Char-to-tile mapping loaded from a RON file next to the map, or from the folder's DEFAULT_LEGEND
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct TileLegend {
+    //Tileset for tiles that do not name their own, DEFAULT_TILESET if left out
+    #[serde(default)]
+    pub tileset: Option<String>,
+    pub tiles: HashMap<char, TileDef>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TileDef {
+    //Identifier importers use to refer to this tile, e.g. an LDtk IntGrid value
+    #[serde(default)]
+    pub name: Option<String>,
+    //Tiles without an atlas index or tile name are placed but not drawn
+    #[serde(default)]
+    pub atlas_index: Option<usize>,
+    //Named tile of the tileset, used when there is no atlas index
+    #[serde(default)]
+    pub tile: Option<String>,
+    //Tileset this tile is drawn from instead of the legend's
+    #[serde(default)]
+    pub tileset: Option<String>,
+    #[serde(default)]
+    pub collider: Option<ColliderShape>,
+    #[serde(default)]
+    pub body: Option<TileBody>,
+    #[serde(default)]
+    pub markers: Vec<TileMarker>,
+    //Path for tiles with the MovingCollider marker
+    #[serde(default)]
+    pub platform: Option<PlatformPath>,
+    //Rise per run of a walkable slope, positive when it rises to the right
+    #[serde(default)]
+    pub slope: Option<f32>,
+    //Points for tiles with the Collectible marker, 1 if left out
+    #[serde(default)]
+    pub value: Option<u32>,
+    //Links switches to the doors and platforms they operate
+    #[serde(default)]
+    pub channel: Option<u32>,
+    //Replaces atlas_index with one picked from the tile's neighbours by a rule set of its tileset
+    #[serde(default)]
+    pub autotile: Option<TileAutotile>,
+    //Timing for tiles with the Crumbling marker
+    #[serde(default)]
+    pub crumble: Option<CrumbleTiming>,
+    //When tiles with the Timed marker are solid
+    #[serde(default)]
+    pub beat: Option<BeatPattern>,
+    //Hits a tile with the Destructible marker takes before breaking, 1 if left out
+    #[serde(default)]
+    pub hit_points: Option<u32>,
+    //Symbol a broken Destructible tile is replaced with, usually a collectible
+    #[serde(default)]
+    pub drop: Option<char>,
+    //Health a tile with the Hazard marker takes from the player, 1 if left out
+    #[serde(default)]
+    pub damage: Option<u32>,
+    #[serde(default)]
+    pub material: Option<TileMaterial>,
+}
+
+//Points are relative to the tile's center
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub enum ColliderShape {
+    Cuboid { half_width: f32, half_height: f32 },
+    Ball { radius: f32 },
+    Triangle { points: [(f32, f32); 3] },
+    Polyline { points: Vec<(f32, f32)> },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum TileBody {
+    Fixed,
+    KinematicPositionBased,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum TileMarker {
+    TileCollider,
+    WallCollider,
+    MovingCollider,
+    EncounterSpawner,
+    LevelGoal,
+    Checkpoint,
+    PlayerStart,
+    Collectible,
+    PressurePlate,
+    Lever,
+    Door,
+    Crumbling,
+    Timed,
+    Destructible,
+    OneWayPlatform,
+    Ladder,
+    Hazard,
+}
+
+//How a MovingCollider travels. Waypoints are cells relative to the platform's own cell, rows growing downwards
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct PlatformPath {
+    pub waypoints: Vec<(i32, i32)>,
+    //Cells per second
+    pub speed: f32,
+    pub easing: Easing,
+    pub mode: PathMode,
+    //Seconds spent at every waypoint, and before setting off for the first time
+    pub pause: f32,
+    pub delay: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum Easing {
+    Linear,
+    EaseIn,
+    EaseOut,
+    EaseInOut,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum PathMode {
+    //Back and forth along the waypoints
+    PingPong,
+    //From the last waypoint straight back to the start
+    Loop,
+    //Stops at the last waypoint
+    Once,
+}
+
+//How long a Crumbling tile holds after the player lands on it, and how long it stays gone
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct CrumbleTiming {
+    pub delay: f32,
+    pub respawn: f32,
+}
+
+//Surface of a tile. Ice is a material with little friction, conveyor belts move what stands on them
+//and bounce pads launch the player upwards
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct TileMaterial {
+    pub friction: f32,
+    pub restitution: f32,
+    //Surface speed, in the same units as Velocity and positive to the right
+    pub conveyor: f32,
+    //Upwards speed the player is launched with on landing
+    pub bounce: Option<f32>,
+}
+
+//A Timed tile is solid for the first `solid` beats of every `period`, starting `offset` beats in
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct BeatPattern {
+    pub period: u32,
+    pub solid: u32,
+    pub offset: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LegendError {
+    Io(String),
+    Parse(String),
+}
+
+impl fmt::Display for LegendError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LegendError::Io(err) => write!(f, "could not read legend: {}", err),
+            LegendError::Parse(err) => write!(f, "invalid legend: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for LegendError {}
+
+impl TileLegend {
+    pub fn parse(source: &str) -> Result<TileLegend, LegendError> {
+        ron::from_str(source).map_err(|err| LegendError::Parse(err.to_string()))
+    }
+
+    //Legend for "assets/map.txt" lives in "assets/map.legend.ron"
+    pub fn path_for(map_path: &Path) -> PathBuf {
+        map_path.with_extension("legend.ron")
+    }
+
+    //Shared by every map in the folder that has no legend of its own
+    pub fn default_path_for(map_path: &Path) -> PathBuf {
+        map_path.with_file_name(DEFAULT_LEGEND)
+    }
+
+    pub fn get(&self, symbol: char) -> Option<&TileDef> {
+        self.tiles.get(&symbol)
+    }
+
+    pub fn symbol_named(&self, name: &str) -> Option<char> {
+        self.tiles
+            .iter()
+            .find(|(_, def)| def.name.as_deref() == Some(name))
+            .map(|(symbol, _)| *symbol)
+    }
+
+    //Lowest symbol carrying the marker, so the choice doesn't depend on HashMap order
+    pub fn symbol_with_marker(&self, marker: TileMarker) -> Option<char> {
+        self.tiles
+            .iter()
+            .filter(|(_, def)| def.markers.contains(&marker))
+            .map(|(symbol, _)| *symbol)
+            .min()
+    }
+
+    pub fn tileset_for<'a>(&'a self, def: &'a TileDef) -> &'a str {
+        def.tileset
+            .as_deref()
+            .or(self.tileset.as_deref())
+            .unwrap_or(DEFAULT_TILESET)
+    }
+
+    //Every tileset the legend's tiles are drawn from
+    pub fn tilesets(&self) -> Vec<&str> {
+        let mut tilesets: Vec<&str> = self.tiles.values().map(|def| self.tileset_for(def)).collect();
+        tilesets.sort_unstable();
+        tilesets.dedup();
+        tilesets
+    }
+}
+
+impl TileDef {
+    //Tiles that never move or disappear can be baked into chunk meshes
+    pub fn is_static(&self) -> bool {
+        self.body != Some(TileBody::KinematicPositionBased)
+            && !self.markers.iter().any(|marker| {
+                matches!(
+                    marker,
+                    TileMarker::MovingCollider
+                        | TileMarker::Collectible
+                        | TileMarker::PressurePlate
+                        | TileMarker::Lever
+                        | TileMarker::Door
+                        | TileMarker::Crumbling
+                        | TileMarker::Timed
+                        | TileMarker::Destructible
+                )
+            })
+    }
+
+    //Static cuboid tiles can share one collider with their neighbours. Tiles the player triggers by
+    //overlapping them are checked around their own translation, so they keep one entity per cell
+    pub fn is_mergeable(&self) -> bool {
+        matches!(self.collider, Some(ColliderShape::Cuboid { .. }))
+            && self.is_static()
+            && !self.markers.iter().any(|marker| {
+                matches!(
+                    marker,
+                    TileMarker::Ladder
+                        | TileMarker::Hazard
+                        | TileMarker::Checkpoint
+                        | TileMarker::LevelGoal
+                        | TileMarker::EncounterSpawner
+                )
+            })
+    }
+}
+
+impl Default for CrumbleTiming {
+    fn default() -> Self {
+        CrumbleTiming { delay: 0.5, respawn: 3.0 }
+    }
+}
+
+//Rapier's defaults
+impl Default for TileMaterial {
+    fn default() -> Self {
+        TileMaterial { friction: 0.5, restitution: 0.0, conveyor: 0.0, bounce: None }
+    }
+}
+
+//Every other beat
+impl Default for BeatPattern {
+    fn default() -> Self {
+        BeatPattern { period: 2, solid: 1, offset: 0 }
+    }
+}
+
+impl BeatPattern {
+    pub fn is_solid(&self, beat: u32) -> bool {
+        self.period == 0 || (beat + self.offset) % self.period < self.solid
+    }
+}
+
+//Nine cells to the right and back, like the platforms always did
+impl Default for PlatformPath {
+    fn default() -> Self {
+        PlatformPath {
+            waypoints: vec![(9, 0)],
+            speed: 2.0,
+            easing: Easing::EaseInOut,
+            mode: PathMode::PingPong,
+            pause: 0.0,
+            delay: 0.0,
+        }
+    }
+}
+
+impl Easing {
+    //Maps linear progress along a segment to eased progress, both in 0..=1
+    pub fn apply(&self, t: f32) -> f32 {
+        match self {
+            Easing::Linear => t,
+            Easing::EaseIn => t * t,
+            Easing::EaseOut => t * (2.0 - t),
+            Easing::EaseInOut => t * t * (3.0 - 2.0 * t),
+        }
+    }
+}
+
+impl ColliderShape {
+    pub fn to_collider(&self) -> Collider {
+        match *self {
+            ColliderShape::Cuboid { half_width, half_height } => Collider::cuboid(half_width, half_height),
+            ColliderShape::Ball { radius } => Collider::ball(radius),
+            ColliderShape::Triangle { points: [a, b, c] } => {
+                Collider::triangle(Vec2::new(a.0, a.1), Vec2::new(b.0, b.1), Vec2::new(c.0, c.1))
+            }
+            ColliderShape::Polyline { ref points } => {
+                Collider::polyline(points.iter().map(|&(x, y)| Vec2::new(x, y)).collect(), None)
+            }
+        }
+    }
+}
+
+//Adds the physics body, collider and marker components a tile declares
+pub fn insert_tile_components(commands: &mut Commands, tile: Entity, def: &TileDef) {
+    let mut entity = commands.entity(tile);
+
+    match def.body {
+        Some(TileBody::Fixed) => {
+            entity.insert(RigidBody::Fixed);
+        }
+        Some(TileBody::KinematicPositionBased) => {
+            entity.insert(RigidBody::KinematicPositionBased)
+            .insert(Velocity {
+                linvel: Vec2::new(0.0, 0.0),
+                angvel: 0.0
+            })
+            .insert(GravityScale(0.1))
+            .insert(Ccd::enabled())
+            .insert(Sleeping::disabled())
+            .insert(LockedAxes::ROTATION_LOCKED);
+        }
+        None => {}
+    }
+
+    if let Some(shape) = &def.collider {
+        entity.insert(shape.to_collider());
+    }
+
+    if let Some(gradient) = def.slope {
+        entity.insert(Slope { gradient });
+    }
+
+    //The lower coefficient wins, so the player's own restitution doesn't make every material bouncy.
+    //Conveyors take the higher friction instead, or the player's would cap how well a belt grips
+    if let Some(material) = &def.material {
+        let friction_rule = if material.conveyor != 0.0 { CoefficientCombineRule::Max } else { CoefficientCombineRule::Min };
+        entity.insert(Friction {
+            coefficient: material.friction,
+            combine_rule: friction_rule,
+        })
+        .insert(Restitution {
+            coefficient: material.restitution,
+            combine_rule: CoefficientCombineRule::Min,
+        });
+
+        if material.conveyor != 0.0 {
+            entity.insert(Conveyor { speed: material.conveyor }).insert(ActiveHooks::MODIFY_SOLVER_CONTACTS);
+        }
+        if let Some(speed) = material.bounce {
+            entity.insert(BouncePad { speed });
+        }
+    }
+
+    for marker in def.markers.iter() {
+        match marker {
+            TileMarker::TileCollider => {
+                entity.insert(TileCollider);
+            }
+            TileMarker::WallCollider => {
+                entity.insert(WallCollider);
+            }
+            TileMarker::MovingCollider => {
+                entity.insert(MovingCollider::new(def.platform.clone().unwrap_or_default()));
+            }
+            TileMarker::EncounterSpawner => {
+                entity.insert(EncounterSpawner);
+            }
+            TileMarker::LevelGoal => {
+                entity.insert(LevelGoal);
+            }
+            TileMarker::Checkpoint => {
+                entity.insert(Checkpoint);
+            }
+            //Taken out of the grid when the level is parsed
+            TileMarker::PlayerStart => {}
+            TileMarker::Collectible => {
+                entity.insert(Collectible { value: def.value.unwrap_or(1) });
+            }
+            //Switches only do something once they are on a channel
+            TileMarker::PressurePlate | TileMarker::Lever => {}
+            TileMarker::Door => {
+                entity.insert(Door::new(def.collider.clone()));
+            }
+            TileMarker::Crumbling => {
+                entity.insert(Crumbling::new(def.collider.clone(), def.crumble.clone().unwrap_or_default()));
+            }
+            TileMarker::Timed => {
+                entity.insert(TimedBlock::new(def.collider.clone(), def.beat.clone().unwrap_or_default()));
+            }
+            TileMarker::Destructible => {
+                entity.insert(Destructible { hit_points: def.hit_points.unwrap_or(1), drop: def.drop });
+            }
+            TileMarker::Hazard => {
+                entity.insert(Hazard { damage: def.damage.unwrap_or(1) });
+            }
+            TileMarker::Ladder => {
+                entity.insert(Ladder);
+            }
+            TileMarker::OneWayPlatform => {
+                entity.insert(OneWayPlatform).insert(ActiveHooks::MODIFY_SOLVER_CONTACTS);
+            }
+        }
+    }
+
+    if let Some(channel) = def.channel {
+        insert_channel_components(commands, tile, def, channel);
+    }
+}
+
+//Makes the tile a switch for the channel if it is one, and otherwise something the channel operates
+pub fn insert_channel_components(commands: &mut Commands, tile: Entity, def: &TileDef, channel: u32) {
+    let kind = if def.markers.contains(&TileMarker::Lever) {
+        Some(SwitchKind::Lever)
+    } else if def.markers.contains(&TileMarker::PressurePlate) {
+        Some(SwitchKind::PressurePlate)
+    } else {
+        None
+    };
+
+    match kind {
+        Some(kind) => commands.entity(tile).insert(Switch { channel, kind }),
+        None => commands.entity(tile).insert(SwitchTarget { channel }),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::system::CommandQueue;
+
+    use super::*;
+
+    fn default_legend() -> TileLegend {
+        TileLegend::parse(include_str!("../assets/default.legend.ron")).unwrap()
+    }
+
+    fn def(source: &str) -> TileDef {
+        ron::from_str(source).unwrap()
+    }
+
+    //Runs insert_tile_components on a fresh entity and returns the world holding it
+    fn spawn(def: &TileDef) -> (World, Entity) {
+        let mut world = World::new();
+        let tile = world.spawn().id();
+        let mut queue = CommandQueue::default();
+        let mut commands = Commands::new(&mut queue, &world);
+        insert_tile_components(&mut commands, tile, def);
+        queue.apply(&mut world);
+        (world, tile)
+    }
+
+    #[test]
+    fn parses_the_default_legend() {
+        let legend = default_legend();
+        let ground = legend.get('#').unwrap();
+
+        assert_eq!(ground.atlas_index, Some(0));
+        assert_eq!(ground.body, Some(TileBody::Fixed));
+        assert_eq!(ground.markers, vec![TileMarker::TileCollider]);
+        assert_eq!(legend.symbol_named("gem"), Some('$'));
+        assert_eq!(legend.symbol_with_marker(TileMarker::PlayerStart), Some('@'));
+        assert_eq!(legend.tilesets(), vec!["ground.tileset.ron"]);
+    }
+
+    #[test]
+    fn fills_in_defaults_for_left_out_fields() {
+        let legend = TileLegend::parse("(tiles: { '-': (markers: [MovingCollider]) })").unwrap();
+        let def = legend.get('-').unwrap();
+
+        assert_eq!(def.atlas_index, None);
+        assert!(def.platform.is_none());
+        assert_eq!(legend.tileset_for(def), DEFAULT_TILESET);
+    }
+
+    #[test]
+    fn reports_invalid_legends() {
+        let unknown_marker = TileLegend::parse("(tiles: { '#': (markers: [Floor]) })");
+        let missing_tiles = TileLegend::parse("(tileset: None)");
+
+        assert!(matches!(unknown_marker, Err(LegendError::Parse(_))));
+        assert!(matches!(missing_tiles, Err(LegendError::Parse(_))));
+        assert_eq!(
+            LegendError::Io("not found".to_string()).to_string(),
+            "could not read legend: not found"
+        );
+    }
+
+    #[test]
+    fn only_static_cuboids_without_touch_markers_merge() {
+        let legend = default_legend();
+        let mergeable = |symbol| legend.get(symbol).unwrap().is_mergeable();
+
+        assert!(mergeable('#'));
+        assert!(mergeable('|'));
+        //Slopes, moving platforms, breakable tiles and climbable walls keep their own colliders
+        assert!(!mergeable('/'));
+        assert!(!mergeable('-'));
+        assert!(!mergeable('C'));
+        assert!(!mergeable('W'));
+        //No collider at all
+        assert!(!mergeable('x'));
+    }
+
+    #[test]
+    fn inserts_body_collider_and_markers() {
+        let (world, tile) = spawn(&def("(collider: Some(Cuboid(half_width: 0.08, half_height: 0.06)), body: Some(Fixed), markers: [TileCollider])"));
+
+        assert!(matches!(world.get::<RigidBody>(tile), Some(RigidBody::Fixed)));
+        assert!(world.get::<Collider>(tile).is_some());
+        assert!(world.get::<TileCollider>(tile).is_some());
+        assert!(world.get::<WallCollider>(tile).is_none());
+    }
+
+    #[test]
+    fn inserts_marker_data_with_its_defaults() {
+        let (world, coin) = spawn(&def("(markers: [Collectible])"));
+        assert_eq!(world.get::<Collectible>(coin).map(|collectible| collectible.value), Some(1));
+
+        let (world, lever) = spawn(&def("(markers: [Lever], channel: Some(2))"));
+        let switch = world.get::<Switch>(lever).unwrap();
+        assert_eq!((switch.channel, switch.kind), (2, SwitchKind::Lever));
+
+        let (world, door) = spawn(&def("(markers: [Door], channel: Some(2))"));
+        assert_eq!(world.get::<SwitchTarget>(door).map(|target| target.channel), Some(2));
+        assert!(world.get::<Switch>(door).is_none());
+
+        let (world, spikes) = spawn(&def("(markers: [Hazard])"));
+        assert_eq!(world.get::<Hazard>(spikes).map(|hazard| hazard.damage), Some(1));
+        let (world, lava) = spawn(&def("(markers: [Hazard], damage: Some(3))"));
+        assert_eq!(world.get::<Hazard>(lava).map(|hazard| hazard.damage), Some(3));
+    }
+
+    #[test]
+    fn conveyors_grip_with_their_own_friction() {
+        let legend = default_legend();
+        let friction = |name: &str| {
+            let (world, tile) = spawn(legend.get(legend.symbol_named(name).unwrap()).unwrap());
+            let friction = world.get::<Friction>(tile).unwrap();
+            (friction.coefficient, friction.combine_rule)
+        };
+
+        //Max wins over the player's Average, so the belt's 1.0 ends up in the contact rather than the player's 0.5
+        assert_eq!(friction("conveyor_left"), (1.0, CoefficientCombineRule::Max));
+        assert_eq!(friction("conveyor_right"), (1.0, CoefficientCombineRule::Max));
+
+        let (world, ice) = spawn(&def("(material: Some((friction: 0.05)))"));
+        assert_eq!(world.get::<Friction>(ice).map(|friction| friction.combine_rule), Some(CoefficientCombineRule::Min));
+    }
+}
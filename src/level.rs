@@ -0,0 +1,312 @@
+use std::{collections::HashMap, fmt};
+
+use bevy::reflect::TypeUuid;
+use serde::Deserialize;
+
+use crate::legend::{LegendError, TileLegend, TileMarker};
+
+//Registry the campaign starts from, relative to the assets folder
+pub const REGISTRY_PATH: &str = "campaign.levels.ron";
+
+//Block of cells in grid coordinates, used for merged colliders
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TileRect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TileData {
+    pub x: usize,
+    pub y: usize,
+    pub symbol: char,
+}
+
+//Parsed level, rows top to bottom. Nothing in here depends on a running App
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LevelData {
+    pub width: usize,
+    pub height: usize,
+    pub grid: Vec<Vec<Option<char>>>,
+    //Cell the player starts in, when the level places one
+    pub player_start: Option<(usize, usize)>,
+    //Waypoint cells for moving platforms, keyed by the platform's own cell
+    pub platform_paths: HashMap<(usize, usize), Vec<(usize, usize)>>,
+    //Switch channels placed in the level editor, keyed by cell, replacing the legend's
+    pub channels: HashMap<(usize, usize), u32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LevelError {
+    Io(String),
+    Parse(String),
+    Legend(LegendError),
+    Import(String),
+    Empty,
+    //Line and column are 1-based so they match what editors show
+    UnknownTile { line: usize, column: usize, symbol: char },
+    //Where a second player start was placed, 1-based like UnknownTile
+    DuplicatePlayerStart { line: usize, column: usize },
+}
+
+impl fmt::Display for LevelError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LevelError::Io(err) => write!(f, "could not read level: {}", err),
+            LevelError::Parse(err) => write!(f, "invalid level registry: {}", err),
+            LevelError::Legend(err) => write!(f, "{}", err),
+            LevelError::Import(err) => write!(f, "could not import level: {}", err),
+            LevelError::Empty => write!(f, "level contains no tiles"),
+            LevelError::UnknownTile { line, column, symbol } => {
+                write!(f, "unknown tile '{}' at line {}, column {}", symbol, line, column)
+            }
+            LevelError::DuplicatePlayerStart { line, column } => {
+                write!(f, "second player start at line {}, column {}", line, column)
+            }
+        }
+    }
+}
+
+impl std::error::Error for LevelError {}
+
+impl LevelData {
+    //Every non-blank char has to be declared in the legend
+    pub fn parse(source: &str, legend: &TileLegend) -> Result<LevelData, LevelError> {
+        let mut grid = Vec::new();
+
+        for (y, line) in source.lines().enumerate() {
+            let mut row = Vec::new();
+
+            for (x, symbol) in line.chars().enumerate() {
+                if symbol == ' ' {
+                    row.push(None);
+                } else if legend.get(symbol).is_some() {
+                    row.push(Some(symbol));
+                } else {
+                    return Err(LevelError::UnknownTile { line: y + 1, column: x + 1, symbol });
+                }
+            }
+
+            grid.push(row);
+        }
+
+        let mut level = LevelData::from_grid(grid)?;
+        level.take_player_start(legend)?;
+        Ok(level)
+    }
+
+    //Used by the importers once they have mapped their tiles to legend symbols
+    pub fn from_grid(grid: Vec<Vec<Option<char>>>) -> Result<LevelData, LevelError> {
+        let level = LevelData {
+            width: grid.iter().map(|row| row.len()).max().unwrap_or(0),
+            height: grid.len(),
+            grid,
+            ..Default::default()
+        };
+
+        if level.tiles().next().is_none() {
+            return Err(LevelError::Empty);
+        }
+
+        Ok(level)
+    }
+
+    //Turns the cell holding a PlayerStart symbol into the player start. The cell is cleared, as the
+    //marker only says where the player begins and is never spawned as a tile
+    pub fn take_player_start(&mut self, legend: &TileLegend) -> Result<(), LevelError> {
+        let is_start = |symbol: char| {
+            legend
+                .get(symbol)
+                .is_some_and(|def| def.markers.contains(&TileMarker::PlayerStart))
+        };
+        let mut starts = Vec::new();
+
+        for (y, row) in self.grid.iter_mut().enumerate() {
+            for (x, cell) in row.iter_mut().enumerate() {
+                if cell.is_some_and(is_start) {
+                    *cell = None;
+                    starts.push((x, y));
+                }
+            }
+        }
+
+        for (x, y) in starts {
+            self.set_player_start(x, y)?;
+        }
+        Ok(())
+    }
+
+    //Every format places at most one player start, wherever it comes from
+    pub fn set_player_start(&mut self, x: usize, y: usize) -> Result<(), LevelError> {
+        if self.player_start.is_some() {
+            return Err(LevelError::DuplicatePlayerStart { line: y + 1, column: x + 1 });
+        }
+        self.player_start = Some((x, y));
+        Ok(())
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> Option<char> {
+        self.grid.get(y).and_then(|row| row.get(x)).copied().flatten()
+    }
+
+    //Greedily covers every cell holding symbol with as few rectangles as possible,
+    //growing each one right along its row first and then down while whole rows match
+    pub fn merged_rects(&self, symbol: char) -> Vec<TileRect> {
+        let mut covered = vec![vec![false; self.width]; self.height];
+        let mut rects = Vec::new();
+        let free = |covered: &Vec<Vec<bool>>, x: usize, y: usize| self.get(x, y) == Some(symbol) && !covered[y][x];
+
+        for y in 0..self.height {
+            for x in 0..self.width {
+                if !free(&covered, x, y) {
+                    continue;
+                }
+
+                let mut width = 1;
+                while x + width < self.width && free(&covered, x + width, y) {
+                    width += 1;
+                }
+
+                let mut height = 1;
+                while y + height < self.height && (x..x + width).all(|column| free(&covered, column, y + height)) {
+                    height += 1;
+                }
+
+                for row in covered.iter_mut().skip(y).take(height) {
+                    for cell in row.iter_mut().skip(x).take(width) {
+                        *cell = true;
+                    }
+                }
+                rects.push(TileRect { x, y, width, height });
+            }
+        }
+
+        rects
+    }
+
+    pub fn tiles_in(&self, bounds: TileRect) -> impl Iterator<Item = TileData> + '_ {
+        self.grid.iter().enumerate().skip(bounds.y).take(bounds.height).flat_map(move |(y, row)| {
+            row.iter()
+                .enumerate()
+                .skip(bounds.x)
+                .take(bounds.width)
+                .filter_map(move |(x, symbol)| symbol.map(|symbol| TileData { x, y, symbol }))
+        })
+    }
+
+    pub fn tiles(&self) -> impl Iterator<Item = TileData> + '_ {
+        self.grid.iter().enumerate().flat_map(|(y, row)| {
+            row.iter()
+                .enumerate()
+                .filter_map(move |(x, symbol)| symbol.map(|symbol| TileData { x, y, symbol }))
+        })
+    }
+}
+
+//Ordered list of levels that make up the campaign, loaded from REGISTRY_PATH
+#[derive(Debug, Clone, Default, Deserialize, TypeUuid)]
+#[uuid = "0b7c2f4e-6d1a-4f3b-8e59-2a4c9d7e1f63"]
+pub struct LevelRegistry {
+    pub levels: Vec<LevelEntry>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct LevelEntry {
+    pub name: String,
+    //Relative to the assets folder. The legend is read from next to the map, see LevelAssetLoader
+    pub path: String,
+    //Level identifier inside an LDtk project, defaults to its first level
+    #[serde(default)]
+    pub level: Option<String>,
+}
+
+impl LevelRegistry {
+    pub fn get(&self, index: usize) -> Option<&LevelEntry> {
+        self.levels.get(index)
+    }
+}
+
+impl LevelEntry {
+    //LDtk levels are labeled sub assets of their project
+    pub fn asset_path(&self) -> String {
+        match &self.level {
+            Some(level) => format!("{}#{}", self.path, level),
+            None => self.path.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LEGEND: &str = r#"(
+        tiles: {
+            '#': (markers: [TileCollider]),
+            '@': (markers: [PlayerStart]),
+        },
+    )"#;
+
+    fn legend() -> TileLegend {
+        TileLegend::parse(LEGEND).unwrap()
+    }
+
+    #[test]
+    fn parses_grid_and_player_start() {
+        let level = LevelData::parse("#  #\n #@\n####", &legend()).unwrap();
+
+        assert_eq!((level.width, level.height), (4, 3));
+        assert_eq!(level.grid[0], vec![Some('#'), None, None, Some('#')]);
+        //The start marker is taken out of the grid
+        assert_eq!(level.grid[1], vec![None, Some('#'), None]);
+        assert_eq!(level.player_start, Some((2, 1)));
+        assert_eq!(level.tiles().count(), 7);
+    }
+
+    #[test]
+    fn reports_unknown_tiles_one_based() {
+        let err = LevelData::parse("##\n#?#", &legend()).unwrap_err();
+
+        assert_eq!(err, LevelError::UnknownTile { line: 2, column: 2, symbol: '?' });
+    }
+
+    #[test]
+    fn rejects_levels_without_tiles() {
+        assert_eq!(LevelData::parse("", &legend()).unwrap_err(), LevelError::Empty);
+        assert_eq!(LevelData::parse("   \n  ", &legend()).unwrap_err(), LevelError::Empty);
+    }
+
+    #[test]
+    fn merges_rows_before_growing_down() {
+        let level = LevelData::parse("###\n###\n#  ", &legend()).unwrap();
+
+        assert_eq!(
+            level.merged_rects('#'),
+            vec![TileRect { x: 0, y: 0, width: 3, height: 2 }, TileRect { x: 0, y: 2, width: 1, height: 1 }]
+        );
+    }
+
+    #[test]
+    fn merged_rects_cover_every_cell_once() {
+        let level = LevelData::parse("## ##\n#####\n #  #", &legend()).unwrap();
+        let rects = level.merged_rects('#');
+        let mut covered = vec![vec![0; level.width]; level.height];
+
+        for rect in rects.iter() {
+            for row in covered.iter_mut().skip(rect.y).take(rect.height) {
+                for cell in row.iter_mut().skip(rect.x).take(rect.width) {
+                    *cell += 1;
+                }
+            }
+        }
+        for (y, row) in covered.iter().enumerate() {
+            for (x, count) in row.iter().enumerate() {
+                let expected = if level.get(x, y) == Some('#') { 1 } else { 0 };
+                assert_eq!(*count, expected, "cell {}, {}", x, y);
+            }
+        }
+        assert!(level.merged_rects('@').is_empty());
+    }
+}
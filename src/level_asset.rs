@@ -0,0 +1,321 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use bevy::{
+    asset::{AssetLoader, BoxedFuture, LoadContext, LoadedAsset},
+    reflect::TypeUuid,
+};
+
+use crate::{
+    ldtk,
+    legend::{LegendError, TileLegend},
+    level::{LevelData, LevelError, LevelRegistry},
+    tiled::{self, TiledFormat},
+};
+
+//A parsed level together with the legend its symbols refer to, and what it was parsed from so it
+//can be rebuilt when the legend or a file the map refers to changes
+#[derive(TypeUuid)]
+#[uuid = "5f0c7d9e-3b1a-4c62-9a8e-1d2f6b7c4e10"]
+pub struct LevelAsset {
+    pub legend: TileLegend,
+    pub level: LevelData,
+    path: PathBuf,
+    source: String,
+    format: MapFormat,
+    //Identifier of the level inside an LDtk project
+    identifier: Option<String>,
+    legend_path: Option<PathBuf>,
+    //Asset paths of the files the map refers to, keyed by the path as written in the map
+    external: HashMap<String, PathBuf>,
+}
+
+//Legend or file a map refers to, loaded on its own so the asset server watches it for changes
+#[derive(TypeUuid)]
+#[uuid = "a3e81c56-2f4d-4b97-8c0a-6d5e9f1b2c74"]
+pub struct LevelFile(pub String);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MapFormat {
+    Ascii,
+    Tiled(TiledFormat),
+    Ldtk,
+}
+
+//Loads ASCII, Tiled and LDtk maps. The legend is read from next to the map, falling back to
+//the folder's default legend. Every level of an LDtk project is added as a sub asset labeled
+//with its identifier, the first one also being the default asset.
+#[derive(Default)]
+pub struct LevelAssetLoader;
+
+#[derive(Default)]
+pub struct LevelFileLoader;
+
+#[derive(Default)]
+pub struct LevelRegistryLoader;
+
+impl AssetLoader for LevelAssetLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext
+    ) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            let path = load_context.path().to_path_buf();
+            let source = std::str::from_utf8(bytes)?;
+            let format = match path.extension().and_then(|extension| extension.to_str()) {
+                Some("tmx") => MapFormat::Tiled(TiledFormat::Tmx),
+                Some("tmj") => MapFormat::Tiled(TiledFormat::Tmj),
+                Some("ldtk") => MapFormat::Ldtk,
+                _ => MapFormat::Ascii,
+            };
+            let (legend_path, legend) = match read_legend(load_context, &path).await? {
+                Some((legend_path, legend)) => (Some(legend_path), Some(legend)),
+                None => (None, None),
+            };
+            let external_files = match format {
+                MapFormat::Tiled(format) => tiled::external_files(source, format)?,
+                MapFormat::Ldtk => ldtk::external_files(source)?,
+                MapFormat::Ascii => Vec::new(),
+            };
+            let directory = path.parent().unwrap_or_else(|| Path::new(""));
+            let external_paths: HashMap<String, PathBuf> =
+                external_files.iter().map(|file| (file.clone(), directory.join(file))).collect();
+            let external = read_external(load_context, &external_paths).await?;
+
+            let levels = parse_map(&path, source, format, legend, &external)?;
+            for (index, (identifier, legend, level)) in levels.into_iter().enumerate() {
+                let level_asset = || LevelAsset {
+                    legend: legend.clone(),
+                    level: level.clone(),
+                    path: path.clone(),
+                    source: source.to_string(),
+                    format,
+                    identifier: identifier.clone(),
+                    legend_path: legend_path.clone(),
+                    external: external_paths.clone(),
+                };
+
+                if index == 0 {
+                    load_context.set_default_asset(LoadedAsset::new(level_asset()));
+                }
+                if let Some(identifier) = &identifier {
+                    load_context.set_labeled_asset(identifier, LoadedAsset::new(level_asset()));
+                }
+            }
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["txt", "tmj", "tmx", "ldtk"]
+    }
+}
+
+impl AssetLoader for LevelFileLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext
+    ) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            let source = String::from_utf8(bytes.to_vec())?;
+            load_context.set_default_asset(LoadedAsset::new(LevelFile(source)));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["legend.ron", "tsx", "tsj", "ldtkl"]
+    }
+}
+
+impl AssetLoader for LevelRegistryLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext
+    ) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            let registry: LevelRegistry = ron::de::from_bytes(bytes).map_err(|err| LevelError::Parse(err.to_string()))?;
+            load_context.set_default_asset(LoadedAsset::new(registry));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["levels.ron"]
+    }
+}
+
+impl LevelAsset {
+    //The legend and files the map refers to, which the level has to be rebuilt from when they change
+    pub fn dependencies(&self) -> impl Iterator<Item = &PathBuf> {
+        self.legend_path.iter().chain(self.external.values())
+    }
+
+    //Parses the map again against the current contents of its dependencies, keyed by asset path
+    pub fn rebuild(&self, files: &HashMap<PathBuf, String>) -> Result<(TileLegend, LevelData), LevelError> {
+        let legend = match self.legend_path.as_ref().and_then(|path| files.get(path)) {
+            Some(source) => Some(TileLegend::parse(source).map_err(LevelError::Legend)?),
+            None => None,
+        };
+        let external = self
+            .external
+            .iter()
+            .filter_map(|(file, path)| files.get(path).map(|source| (file.clone(), source.clone())))
+            .collect();
+
+        parse_map(&self.path, &self.source, self.format, legend, &external)?
+            .into_iter()
+            .find(|(identifier, _, _)| *identifier == self.identifier)
+            .map(|(_, legend, level)| (legend, level))
+            .ok_or_else(|| LevelError::Import(format!("{} no longer holds this level", self.path.display())))
+    }
+}
+
+//Every level a map holds, with the LDtk identifier and the legend it was parsed against
+fn parse_map(
+    path: &Path,
+    source: &str,
+    format: MapFormat,
+    legend: Option<TileLegend>,
+    external: &HashMap<String, String>
+) -> Result<Vec<(Option<String>, TileLegend, LevelData)>, LevelError> {
+    match format {
+        MapFormat::Tiled(format) => {
+            //Tiled maps can describe every tile through properties, so the legend is optional
+            let (legend, level) = tiled::parse(source, format, &legend.unwrap_or_default(), external)?;
+            Ok(vec![(None, legend, level)])
+        }
+        MapFormat::Ldtk => {
+            let legend = legend.ok_or_else(|| missing_legend(path))?;
+            Ok(ldtk::parse(source, &legend, external)?
+                .into_iter()
+                .map(|(identifier, level)| (Some(identifier), legend.clone(), level))
+                .collect())
+        }
+        MapFormat::Ascii => {
+            let legend = legend.ok_or_else(|| missing_legend(path))?;
+            let level = LevelData::parse(source, &legend)?;
+            Ok(vec![(None, legend, level)])
+        }
+    }
+}
+
+//The map's own legend, or the default legend of its folder, if either exists
+async fn read_legend(load_context: &LoadContext<'_>, map_path: &Path) -> Result<Option<(PathBuf, TileLegend)>, LevelError> {
+    for legend_path in legend_paths(map_path) {
+        if let Ok(bytes) = load_context.read_asset_bytes(&legend_path).await {
+            let source = String::from_utf8(bytes).map_err(|err| LevelError::Legend(LegendError::Parse(err.to_string())))?;
+            let legend = TileLegend::parse(&source).map_err(LevelError::Legend)?;
+            return Ok(Some((legend_path, legend)));
+        }
+    }
+    Ok(None)
+}
+
+//Where a map's legend is looked for, in order
+fn legend_paths(map_path: &Path) -> [PathBuf; 2] {
+    [TileLegend::path_for(map_path), TileLegend::default_path_for(map_path)]
+}
+
+//Reads the files a map refers to, keyed by the path as written in the map
+async fn read_external(
+    load_context: &LoadContext<'_>,
+    paths: &HashMap<String, PathBuf>
+) -> Result<HashMap<String, String>, LevelError> {
+    let mut external = HashMap::new();
+
+    for (file, path) in paths {
+        let bytes = load_context
+            .read_asset_bytes(path)
+            .await
+            .map_err(|err| LevelError::Io(format!("{}: {}", file, err)))?;
+        let source = String::from_utf8(bytes).map_err(|err| LevelError::Io(format!("{}: {}", file, err)))?;
+        external.insert(file.clone(), source);
+    }
+    Ok(external)
+}
+
+fn missing_legend(map_path: &Path) -> LevelError {
+    let [own, default] = legend_paths(map_path);
+    LevelError::Legend(LegendError::Io(format!("neither {} nor {} exists", own.display(), default.display())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DEFAULT_LEGEND: &str = include_str!("../assets/default.legend.ron");
+
+    fn default_legend() -> TileLegend {
+        TileLegend::parse(DEFAULT_LEGEND).unwrap()
+    }
+
+    fn ascii_asset(source: &str) -> LevelAsset {
+        let path = PathBuf::from("maps/cave.txt");
+        let legend = default_legend();
+        LevelAsset {
+            level: LevelData::parse(source, &legend).unwrap(),
+            legend,
+            path,
+            source: source.to_string(),
+            format: MapFormat::Ascii,
+            identifier: None,
+            legend_path: Some(PathBuf::from("maps/default.legend.ron")),
+            external: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn looks_for_the_own_legend_before_the_default_one() {
+        assert_eq!(
+            legend_paths(Path::new("maps/cave.txt")),
+            [PathBuf::from("maps/cave.legend.ron"), PathBuf::from("maps/default.legend.ron")]
+        );
+    }
+
+    #[test]
+    fn parses_the_bundled_maps() {
+        let source = include_str!("../assets/map.txt");
+        let map = parse_map(Path::new("map.txt"), source, MapFormat::Ascii, Some(default_legend()), &HashMap::new()).unwrap();
+        assert_eq!(map.len(), 1);
+        assert!(map[0].2.tiles().next().is_some());
+
+        let source = include_str!("../assets/caves.ldtk");
+        let caves = parse_map(Path::new("caves.ldtk"), source, MapFormat::Ldtk, Some(default_legend()), &HashMap::new()).unwrap();
+        assert_eq!(caves[0].0.as_deref(), Some("Caves"));
+    }
+
+    #[test]
+    fn ascii_and_ldtk_maps_need_a_legend() {
+        let err = parse_map(Path::new("maps/cave.txt"), "#", MapFormat::Ascii, None, &HashMap::new()).unwrap_err();
+        assert_eq!(
+            err,
+            LevelError::Legend(LegendError::Io("neither maps/cave.legend.ron nor maps/default.legend.ron exists".to_string()))
+        );
+
+        let source = include_str!("../assets/caves.ldtk");
+        let ldtk = parse_map(Path::new("caves.ldtk"), source, MapFormat::Ldtk, None, &HashMap::new());
+        assert!(matches!(ldtk, Err(LevelError::Legend(LegendError::Io(_)))));
+    }
+
+    #[test]
+    fn rebuilds_against_the_changed_legend() {
+        let asset = ascii_asset("##\n#|");
+        assert_eq!(asset.dependencies().collect::<Vec<_>>(), vec![&PathBuf::from("maps/default.legend.ron")]);
+
+        //'|' is no longer a wall
+        let changed = DEFAULT_LEGEND.replace("name: Some(\"wall\")", "name: Some(\"pillar\")");
+        let files = HashMap::from([(PathBuf::from("maps/default.legend.ron"), changed)]);
+        let (legend, level) = asset.rebuild(&files).unwrap();
+        assert_eq!(legend.symbol_named("pillar"), Some('|'));
+        assert_eq!(level, asset.level);
+
+        let broken = HashMap::from([(PathBuf::from("maps/default.legend.ron"), "(tiles: {".to_string())]);
+        assert!(matches!(asset.rebuild(&broken), Err(LevelError::Legend(LegendError::Parse(_)))));
+    }
+}
@@ -1,5 +1,5 @@
 #![allow(clippy::redunant_field_names)]
-use bevy::{prelude::*, render::camera::ScalingMode};
+use bevy::{prelude::*, asset::AssetServerSettings, render::camera::ScalingMode};
 use bevy_rapier2d::prelude::*;
 use benimator::{*, AnimationPlugin};
 use bevy_kira_audio::{Audio, AudioPlugin};
@@ -10,17 +10,34 @@ use bevy_parallax::{
 pub const CLEAR: Color = Color::rgb(0.2, 0.2, 0.2);
 pub const RESOLUTION: f32 = 16.0 / 9.0;
 pub const TILE_SIZE: f32 = 0.1;
+//Scale between world units and the physics world, contact hooks work in physics units
+pub const PIXELS_PER_METER: f32 = 10.0;
 
 mod player;
 mod debug;
 mod spriteloader;
 mod tilemap;
+mod level;
+mod legend;
+mod tiled;
+mod ldtk;
+mod chunk;
+mod level_asset;
+mod health;
+mod collectible;
+mod autotile;
+mod tileset;
+mod destructible;
 
-use bevy_rapier2d::{plugin::{RapierPhysicsPlugin, NoUserData}, prelude::{RapierDebugRenderPlugin, Restitution}};
+use bevy_rapier2d::{plugin::RapierPhysicsPlugin, prelude::{RapierDebugRenderPlugin, Restitution}};
 use player::{PlayerPlugin, Animations};
 use debug::DebugPlugin;
 use spriteloader::AsciiPlugin;
-use tilemap::TileMapPlugin;
+use tileset::TilesetPlugin;
+use tilemap::{TileMapPlugin, TileHookData};
+use health::HealthPlugin;
+use collectible::CollectiblePlugin;
+use destructible::DestructiblePlugin;
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Copy)]
 pub enum GameState {
@@ -86,17 +103,26 @@ fn main() {
         resizable: false,
         .. Default::default()
     }) 
+    //Lets the tilemap rebuild levels as they are edited
+    .insert_resource(AssetServerSettings {
+        watch_for_changes: true,
+        ..Default::default()
+    })
     .add_plugin(ParallaxPlugin)
     .add_plugins(DefaultPlugins)
     .add_startup_system(spawn_camera)
     .add_startup_system(start_background_audio)
     .add_system(move_parallax_system)
-    .add_plugin(RapierPhysicsPlugin::<NoUserData>::pixels_per_meter(10.0))
+    .add_plugin(RapierPhysicsPlugin::<TileHookData>::pixels_per_meter(PIXELS_PER_METER))
     //.add_plugin(RapierDebugRenderPlugin::default())
+    .add_plugin(TilesetPlugin)
     .add_plugin(AsciiPlugin)
     .add_plugin(PlayerPlugin)
     .add_plugin(DebugPlugin)
     .add_plugin(TileMapPlugin)
+    .add_plugin(HealthPlugin)
+    .add_plugin(CollectiblePlugin)
+    .add_plugin(DestructiblePlugin)
     .add_plugin(AudioPlugin)
     .add_plugin(AnimationPlugin::default()) 
     .run();
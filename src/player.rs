@@ -1,14 +1,27 @@
 use std::time::Duration;
 
-use bevy::{prelude::*, sprite::collide_aabb::collide};
+use bevy::{prelude::*, ecs::system::SystemParam, math::const_vec3, sprite::collide_aabb::collide};
 use bevy_inspector_egui::Inspectable;
 use bevy_rapier2d::{prelude::*, rapier::prelude::RigidBodyVelocity};
 use benimator::*;
 
-use crate::{ spriteloader::{AsciiSheet, spawn_sprite}, TILE_SIZE, tilemap::{TileCollider, EncounterSpawner}, GameState};
+use crate::{ health::{Health, Knockback, PLAYER_MAX_HEALTH}, TILE_SIZE, tilemap::{BouncePad, TileCollider, EncounterSpawner, Ladder, LevelStart, MovingCollider, Slope}, GameState};
 
 pub struct PlayerPlugin;
 
+pub const PLAYER_START: Vec3 = const_vec3!([0.1, -0.2, 900.0]);
+
+pub const PLAYER_GRAVITY_SCALE: f32 = 0.1;
+
+const JUMP_SPEED: f32 = 2.5;
+//Rising faster than this on a slope means a jump or bounce pad launched the player this frame, before
+//player.jumping could be read. Walking up a slope rises at the walking speed times its gradient
+const SLOPE_RELEASE_SPEED: f32 = 2.0;
+
+const CLIMB_SPEED: f32 = 0.8;
+//Ladder hitboxes are a bit wider than a tile so climbable walls can be grabbed from beside their collider
+const CLIMB_REACH: f32 = TILE_SIZE * 1.6;
+
 #[derive(Component, Default, Reflect)]
 #[reflect(Component)]
 pub struct EncounterTracker {
@@ -31,6 +44,35 @@ pub struct Player {
     player_action: PlayerAction,
 }
 
+impl Default for Player {
+    fn default() -> Self {
+        Player {
+            just_moved: false,
+            active: true,
+            speed: 100.0,
+            jumping: false,
+            facing_right: true,
+            player_action: PlayerAction::Idle,
+        }
+    }
+}
+
+impl Player {
+    //Back to standing still, like right after being spawned
+    pub fn reset(&mut self) {
+        self.active = true;
+        self.just_moved = false;
+        self.jumping = false;
+        self.player_action = PlayerAction::Idle;
+    }
+}
+
+//One-way platforms let the player fall through while this is on
+#[derive(Component)]
+pub struct DroppingThrough {
+    timer: Timer,
+}
+
 #[derive(Component, Default, Reflect)]
 #[reflect(Component)]
 pub struct PlayerBullet {
@@ -38,6 +80,12 @@ pub struct PlayerBullet {
     timer: Timer
 }
 
+//Sent when a melee swing starts, for whatever it hits
+pub struct MeleeStrike {
+    pub origin: Vec3,
+    pub facing_right: bool,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Copy, Inspectable)]
 pub enum PlayerAction {
     RunningRight,
@@ -45,6 +93,7 @@ pub enum PlayerAction {
     Idle,
     Jumping,
     Attacking,
+    Climbing,
 }
 
 #[derive(Component, Default)]
@@ -53,6 +102,7 @@ pub struct Animations {
     idle: Handle<SpriteSheetAnimation>,
     jumping: Handle<SpriteSheetAnimation>,
     attacking: Handle<SpriteSheetAnimation>,
+    climbing: Handle<SpriteSheetAnimation>,
 }
 
 
@@ -61,21 +111,27 @@ pub struct PlayerSheet(Handle<TextureAtlas>);
 impl Plugin for PlayerPlugin {
     fn build(&self, app: &mut App) {
         app
+        .add_event::<MeleeStrike>()
         .add_system_set(
             SystemSet::on_enter(GameState::Overworld).with_system(show_player))
         .add_system_set(
             SystemSet::on_exit(GameState::Overworld).with_system(hide_player))
         .add_system_set(
             SystemSet::on_update(GameState::Overworld)
-                .with_system(player_movement)
+                .with_system(player_movement.label("player_movement"))
+                .with_system(climb_ladders.after("player_movement").before("change_animation"))
                 .with_system(cancel_jump)
                 .with_system(player_jump)
+                .with_system(stop_dropping_through)
                 .with_system(shooting)
                 .with_system(move_bullets)
                 .with_system(player_encounter_checking)
                 .with_system(camera_follow)
-                .with_system(change_animation)
+                .with_system(change_animation.label("change_animation"))
                 .with_system(melee_attack)
+                .with_system(move_to_level_start)
+                .with_system(ride_platforms.after("move_platforms"))
+                .with_system(bounce_on_pads)
     )
     .add_startup_system(load_sheet);
     }
@@ -93,15 +149,22 @@ fn camera_follow(
     camera_transform.translation.y = player_transform.translation.y + 0.32;
 }
 
+type MovingPlayer<'a> = (Entity, &'a mut Player, &'a mut Transform, &'a mut GravityScale, Option<&'a DroppingThrough>);
+
 //Player Movement
 fn player_movement(
-    mut player_query: Query<(&mut Player, &mut Transform)>,
+    mut player_query: Query<MovingPlayer, Without<Knockback>>,
     wall_query: Query<&Transform, (With<TileCollider>, Without<Player>)>,
+    slope_query: Query<&Slope>,
+    rapier_context: Res<RapierContext>,
     keyboard: Res<Input<KeyCode>>,
     mut velocities: Query<&mut Velocity>,
     time: Res<Time>
 ) {
-    let (mut player, mut transform) = player_query.single_mut();
+    let (entity, mut player, mut transform, mut gravity_scale, dropping_through) = match player_query.get_single_mut() {
+        Ok(player) => player,
+        Err(_) => return,
+    };
     if !player.active {
         return;
     }
@@ -111,7 +174,9 @@ fn player_movement(
 
         if keyboard.pressed(KeyCode::S) {
             for mut vel in velocities.iter_mut() {
-                vel.linvel = Vec2::new(0.0, 0.0);
+                //Keep falling when dropping through a platform
+                let y = if dropping_through.is_some() { vel.linvel.y } else { 0.0 };
+                vel.linvel = Vec2::new(0.0, y);
             }
 
         } else if keyboard.pressed(KeyCode::A) {
@@ -119,7 +184,7 @@ fn player_movement(
                 vel.linvel = Vec2::new(-movement, vel.linvel.y);
                 player.facing_right = false;
                 transform.rotation = Quat::from_rotation_y(3.0);
-                if !player.jumping && player.player_action != PlayerAction::Attacking {
+                if !player.jumping && player.player_action != PlayerAction::Attacking && player.player_action != PlayerAction::Climbing {
                     player.player_action = PlayerAction::RunningLeft;
                 }
             }
@@ -128,7 +193,7 @@ fn player_movement(
             for mut vel in velocities.iter_mut() {
                 vel.linvel = Vec2::new(movement, vel.linvel.y);
                 player.facing_right = true;
-                if !player.jumping && player.player_action != PlayerAction::Attacking {
+                if !player.jumping && player.player_action != PlayerAction::Attacking && player.player_action != PlayerAction::Climbing {
                     player.player_action = PlayerAction::RunningRight;
                 }
             }
@@ -138,11 +203,27 @@ fn player_movement(
             if player.facing_right == false {
                 transform.rotation = Quat::from_rotation_y(3.0);
             }
-            if !player.jumping && player.player_action != PlayerAction::Attacking {
+            if !player.jumping && player.player_action != PlayerAction::Attacking && player.player_action != PlayerAction::Climbing {
                 player.player_action = PlayerAction::Idle;
             }
         }
 
+        //Follow the slope when walking down it and stand still on it when idle
+        gravity_scale.0 = PLAYER_GRAVITY_SCALE;
+        if let (Some(gradient), Ok(mut velocity)) = (slope_under(entity, &rapier_context, &slope_query), velocities.get_mut(entity)) {
+            let just_jumped = velocity.linvel.y > SLOPE_RELEASE_SPEED;
+            let walking = keyboard.pressed(KeyCode::A) || keyboard.pressed(KeyCode::D);
+
+            if !player.jumping && !just_jumped && dropping_through.is_none() {
+                if !walking {
+                    gravity_scale.0 = 0.0;
+                    velocity.linvel = Vec2::new(0.0, 0.0);
+                } else {
+                    velocity.linvel.y = velocity.linvel.x * gradient;
+                }
+            }
+        }
+
         if x_delta != 0.0 || y_delta != 0.0 {
             player.just_moved = true;
         }
@@ -152,22 +233,102 @@ fn player_movement(
         transform.translation = target;
 }
 
+//Up or down on a ladder starts climbing, which suspends gravity until the player jumps or leaves the ladder
+fn climb_ladders(
+    keyboard: Res<Input<KeyCode>>,
+    mut player_query: Query<(&mut Player, &Transform, &mut GravityScale, &mut Velocity)>,
+    ladder_query: Query<&Transform, (With<Ladder>, Without<Player>)>
+) {
+    let (mut player, transform, mut gravity_scale, mut velocity) = match player_query.get_single_mut() {
+        Ok(player) => player,
+        Err(_) => return,
+    };
+    let climbing = player.player_action == PlayerAction::Climbing;
+
+    let on_ladder = ladder_query.iter().any(|ladder| {
+        collide(
+            transform.translation,
+            Vec2::splat(TILE_SIZE * 0.9),
+            ladder.translation,
+            Vec2::splat(CLIMB_REACH)
+        ).is_some()
+    });
+
+    if !on_ladder || keyboard.pressed(KeyCode::Space) {
+        if climbing {
+            player.player_action = PlayerAction::Idle;
+        }
+        return;
+    }
+
+    let up = keyboard.pressed(KeyCode::W) || keyboard.pressed(KeyCode::Up);
+    let down = keyboard.pressed(KeyCode::S) || keyboard.pressed(KeyCode::Down);
+    if !climbing && !up && !down {
+        return;
+    }
+
+    let mut speed = 0.0;
+    if up {
+        speed = CLIMB_SPEED;
+    } else if down {
+        speed = -CLIMB_SPEED;
+    }
+
+    player.player_action = PlayerAction::Climbing;
+    player.jumping = false;
+    gravity_scale.0 = 0.0;
+    velocity.linvel = Vec2::new(velocity.linvel.x, speed);
+}
+
+//Gradient of a slope the player is touching
+fn slope_under(player: Entity, rapier_context: &RapierContext, slope_query: &Query<&Slope>) -> Option<f32> {
+    rapier_context
+        .contacts_with(player)
+        .filter(|contact_pair| contact_pair.has_any_active_contacts())
+        .map(|contact_pair| if contact_pair.collider1() == player { contact_pair.collider2() } else { contact_pair.collider1() })
+        .find_map(|other| slope_query.get(other).ok())
+        .map(|slope| slope.gradient)
+}
+
 fn player_jump(
- mut player: Query<&mut Player>,
+ mut commands: Commands,
+ mut player: Query<(Entity, &mut Player)>,
  keyboard: Res<Input<KeyCode>>,
  mut velocities: Query<&mut Velocity, With<Player>>
 ) {
-    let mut player = player.single_mut();
+    let (entity, mut player) = player.single_mut();
 
-    if keyboard.pressed(KeyCode::Space) && !player.jumping {
+    //Down and jump drops through one-way platforms instead
+    if keyboard.pressed(KeyCode::S) && keyboard.just_pressed(KeyCode::Space) {
+        for mut vel in velocities.iter_mut() {
+            vel.linvel = Vec2::new(vel.linvel.x, -1.0);
+        }
+        commands.entity(entity).insert(DroppingThrough {
+            timer: Timer::from_seconds(0.3, false)
+        });
+    } else if keyboard.pressed(KeyCode::Space) && !keyboard.pressed(KeyCode::S) && !player.jumping {
         for mut vel in velocities.iter_mut() {
             player.player_action = PlayerAction::Jumping;
-            vel.linvel = Vec2::new(0.0, 2.5);
+            vel.linvel = Vec2::new(0.0, JUMP_SPEED);
             player.jumping = true;
         }
     }
 }
 
+fn stop_dropping_through(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut dropping_query: Query<(Entity, &mut DroppingThrough)>
+) {
+    for (entity, mut dropping_through) in dropping_query.iter_mut() {
+        dropping_through.timer.tick(time.delta());
+
+        if dropping_through.timer.finished() {
+            commands.entity(entity).remove::<DroppingThrough>();
+        }
+    }
+}
+
 fn cancel_jump(
     rapier_context: Res<RapierContext>,
     mut player_query: Query<Entity, With<Player>>,
@@ -189,23 +350,88 @@ fn cancel_jump(
 
 }
 
+//Carries the player along with the moving platform it is standing on
+fn ride_platforms(
+    rapier_context: Res<RapierContext>,
+    mut player_query: Query<(Entity, &mut Transform), With<Player>>,
+    platform_query: Query<(Entity, &MovingCollider, &Transform), Without<Player>>
+) {
+    let (player, mut player_transform) = match player_query.get_single_mut() {
+        Ok(player) => player,
+        Err(_) => return,
+    };
+
+    for (platform, moving_collider, platform_transform) in platform_query.iter() {
+        if standing_on(&rapier_context, player, player_transform.translation, platform, platform_transform.translation) {
+            player_transform.translation += moving_collider.displacement();
+            return;
+        }
+    }
+}
+
+//Landing on a bounce pad launches the player as if it jumped, only higher
+fn bounce_on_pads(
+    rapier_context: Res<RapierContext>,
+    mut player_query: Query<(Entity, &mut Player, &Transform, &mut Velocity)>,
+    pad_query: Query<(Entity, &BouncePad, &Transform), Without<Player>>
+) {
+    let (entity, mut player, player_transform, mut velocity) = match player_query.get_single_mut() {
+        Ok(player) => player,
+        Err(_) => return,
+    };
+
+    for (pad, bounce_pad, pad_transform) in pad_query.iter() {
+        if standing_on(&rapier_context, entity, player_transform.translation, pad, pad_transform.translation) {
+            velocity.linvel.y = bounce_pad.speed;
+            player.player_action = PlayerAction::Jumping;
+            player.jumping = true;
+            return;
+        }
+    }
+}
+
+//Places the player at the start of a freshly loaded level
+fn move_to_level_start(
+    level_start: Option<Res<LevelStart>>,
+    mut player_query: Query<(&mut Player, &mut Transform, &mut Velocity, &mut GravityScale)>
+) {
+    let level_start = match level_start {
+        Some(level_start) if level_start.is_changed() => level_start,
+        _ => return,
+    };
+
+    for (mut player, mut transform, mut velocity, mut gravity_scale) in player_query.iter_mut() {
+        transform.translation = level_start.0;
+        velocity.linvel = Vec2::new(0.0, 0.0);
+        gravity_scale.0 = PLAYER_GRAVITY_SCALE;
+        player.reset();
+    }
+}
+
+//The player's components a melee attack moves and times
+#[derive(SystemParam)]
+struct AttackingPlayer<'w, 's> {
+    velocities: Query<'w, 's, &'static mut Velocity, With<Player>>,
+    attack_timer_query: Query<'w, 's, &'static mut AttackTimer>,
+    player_transform_query: Query<'w, 's, &'static mut Transform, With<Player>>,
+    player_query: Query<'w, 's, &'static mut Player>,
+}
+
 //Melee
 fn melee_attack(
     time: Res<Time>,
-    mut velocities: Query<&mut Velocity, With<Player>>,
-    mut attack_timer_query: Query<&mut AttackTimer>,
-    mut player_transform_query: Query<&mut Transform, With<Player>>,
-    mut player_query: Query<&mut Player>,
+    mut attacking: AttackingPlayer,
     mut commands: Commands,
     buttons: Res<Input<MouseButton>>,
+    mut strike_events: EventWriter<MeleeStrike>,
 ) {
-    let mut player = player_query.single_mut();
+    let mut player = attacking.player_query.single_mut();
     let player_facing_right = player.facing_right;
-    let mut attack_timer = attack_timer_query.single_mut();
+    let mut attack_timer = attacking.attack_timer_query.single_mut();
     
     let player_action = player.player_action;
 
-    let mut player_transform = player_transform_query.single_mut();
+    let mut player_transform = attacking.player_transform_query.single_mut();
 
     if player_action == PlayerAction::Attacking {
 
@@ -225,10 +451,15 @@ fn melee_attack(
                 velocity = -velocity
             }
 
-            for mut vel in velocities.iter_mut() {
+            for mut vel in attacking.velocities.iter_mut() {
                 vel.linvel = Vec2::new(velocity, 0.0);
             }
 
+            strike_events.send(MeleeStrike {
+                origin: player_transform.translation,
+                facing_right: player_facing_right,
+            });
+
             attack_timer.timer.reset();
         } 
     }  
@@ -295,8 +526,22 @@ fn move_bullets(
     }
 }
 
-//Player wall collision
-fn wall_collision_check(
+//Whether the player rests on top of tile rather than touching it from the side or below
+pub fn standing_on(
+    rapier_context: &RapierContext,
+    player: Entity,
+    player_translation: Vec3,
+    tile: Entity,
+    tile_translation: Vec3
+) -> bool {
+    player_translation.y > tile_translation.y
+        && rapier_context
+            .contact_pair(player, tile)
+            .is_some_and(|contact_pair| contact_pair.has_any_active_contacts())
+}
+
+//Player wall collision, also used for anything the player touches to trigger
+pub fn wall_collision_check(
     target_player_pos: Vec3,
     wall_translation: Vec3
 ) -> bool {
@@ -355,6 +600,7 @@ fn change_animation(
         PlayerAction::Jumping => *animation = animations.jumping.clone(),
         PlayerAction::Idle => *animation = animations.idle.clone(),
         PlayerAction::Attacking => *animation = animations.attacking.clone(),
+        PlayerAction::Climbing => *animation = animations.climbing.clone(),
 
         _ => *animation = animations.idle.clone()
     }
@@ -394,17 +640,26 @@ fn spawn_player(mut handles: ResMut<Animations>, mut commands: Commands, player_
             Duration::from_millis(50),
         ));
 
+    //Climbing animation
+    let climbing = animations.add(
+        SpriteSheetAnimation::from_range(
+            9..=9,
+            Duration::from_millis(100),
+        ));
+
     let animations_handler = Animations {
         running: running.clone(),
         idle: idle.clone(),
         jumping: jumping.clone(),
-        attacking: attacking.clone()
+        attacking: attacking.clone(),
+        climbing: climbing.clone()
     };
 
     handles.idle = idle.clone();
     handles.running = running.clone();
     handles.jumping = jumping.clone();
     handles.attacking = attacking.clone();
+    handles.climbing = climbing.clone();
 
     let attack_timer = AttackTimer {
         timer: Timer::from_seconds(0.150, false)
@@ -423,15 +678,9 @@ fn spawn_player(mut handles: ResMut<Animations>, mut commands: Commands, player_
         //.insert(animations_handler)
         .insert(Play)
         .insert(Name::new("Player"))
-        .insert(Player {
-            just_moved: false,
-            active: true,
-            speed: 100.0,
-            jumping: false,
-            facing_right: true,
-            player_action: PlayerAction::Idle,
-        })
+        .insert(Player::default())
         .insert(attack_timer)
+        .insert(Health::new(PLAYER_MAX_HEALTH))
         .insert(EncounterTracker {
             timer: Timer::from_seconds(1.0, true)
         })
@@ -439,7 +688,7 @@ fn spawn_player(mut handles: ResMut<Animations>, mut commands: Commands, player_
         .insert(Collider::ball(6.7))
         .insert(ActiveEvents::COLLISION_EVENTS)
         .insert(Restitution::coefficient(0.7))
-        .insert(GravityScale(0.1))
+        .insert(GravityScale(PLAYER_GRAVITY_SCALE))
         .insert(Ccd::enabled())
         .insert(Velocity {
             linvel: Vec2::new(0.0, 0.0),
@@ -449,7 +698,7 @@ fn spawn_player(mut handles: ResMut<Animations>, mut commands: Commands, player_
         .insert(LockedAxes::ROTATION_LOCKED)
         .insert(Transform {
             scale: Vec3::new(0.01, 0.01, 0.1),
-            translation: Vec3::new(0.1, -0.2, 900.0),
+            translation: PLAYER_START,
             ..Default::default()
         });
 
@@ -499,7 +748,6 @@ fn player_encounter_checking(
     mut player_query: Query<(&mut Player, &mut EncounterTracker, &Transform)>,
     encounter_query: Query<&mut Transform, (With<EncounterSpawner>, Without<Player>)>,
     mut state: ResMut<State<GameState>>,
-    ascii: Res<AsciiSheet>,
     mut time: Res<Time>,
     
 ) {
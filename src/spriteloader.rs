@@ -1,10 +1,11 @@
 use bevy::prelude::*;
 
-use crate::TILE_SIZE;
+use crate::{tileset::{Tilesets, DEFAULT_TILESET}, TILE_SIZE};
 
 pub struct AsciiPlugin;
 
-pub struct AsciiSheet(Handle<TextureAtlas>);
+//Tile sprites are drawn larger than the grid spacing
+pub const TILE_SPRITE_SIZE: f32 = TILE_SIZE * 3.0;
 
 impl Plugin for AsciiPlugin {
     fn build(&self, app: &mut App) {
@@ -12,24 +13,20 @@ impl Plugin for AsciiPlugin {
     }
 }
 
-pub fn get_zero(ascii: Res<'_, AsciiSheet>) -> Handle<TextureAtlas> {
-    ascii.0.clone()
-}
-
 pub fn spawn_sprite(
     commands: &mut Commands,
-    ascii: &AsciiSheet,
+    atlas: &Handle<TextureAtlas>,
     index: usize,
     color: Color,
     translation: Vec3
 ) -> Entity { 
     let mut sprite = TextureAtlasSprite::new(index);
     sprite.color = color;
-    sprite.custom_size = Some(Vec2::splat(TILE_SIZE * 3.0));
+    sprite.custom_size = Some(Vec2::splat(TILE_SPRITE_SIZE));
 
     commands.spawn_bundle(SpriteSheetBundle {
         sprite: sprite,
-        texture_atlas: ascii.0.clone(),
+        texture_atlas: atlas.clone(),
         transform: Transform {
             translation: translation,
             ..Default::default()
@@ -38,23 +35,9 @@ pub fn spawn_sprite(
     }).id()
 }
 
+//Starts loading the default tileset before the first level asks for it
 fn load_stage_sprite(
-    mut commands: Commands,
     assets: Res<AssetServer>,
-    mut texture_atlases: ResMut<Assets<TextureAtlas>>) {
-        let image = assets.load("ground.png");
-        let atlas = TextureAtlas::from_grid_with_padding(
-            image,
-            Vec2::splat(32.0),
-            2,
-            2,
-            Vec2::splat(2.0)
-        );
-
-        let atlas_handle = texture_atlases.add(atlas);
-
-        for f in texture_atlases.iter() {
-            println!("{}", 1);
-        }
-        commands.insert_resource(AsciiSheet(atlas_handle));
-}
\ No newline at end of file
+    mut tilesets: ResMut<Tilesets>) {
+        tilesets.request(&assets, DEFAULT_TILESET);
+}
@@ -0,0 +1,769 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::{
+    legend::{ColliderShape, TileBody, TileDef, TileLegend, TileMarker, TileMaterial},
+    level::{LevelData, LevelError},
+};
+
+//Tiled stores flip/rotation flags in the top bits of every gid
+const GID_FLAGS: u32 = 0xF000_0000;
+
+//Symbols for tiles that are only described by Tiled properties are taken from the private use area
+const GENERATED_SYMBOL_START: u32 = 0xE000;
+
+//Object type that marks where the player begins
+const PLAYER_START: &str = "PlayerStart";
+
+type Properties = HashMap<String, String>;
+
+//Format independent view of a Tiled map, filled from either .tmj or .tmx
+#[derive(Debug, Default)]
+struct TiledMap {
+    width: usize,
+    height: usize,
+    tile_width: f32,
+    tile_height: f32,
+    layers: Vec<TiledLayer>,
+    tilesets: Vec<TiledTileset>,
+}
+
+#[derive(Debug)]
+enum TiledLayer {
+    Tiles { name: String, data: Vec<u32> },
+    Objects { name: String, objects: Vec<TiledObject> },
+}
+
+#[derive(Debug, Default)]
+struct TiledTileset {
+    first_gid: u32,
+    tiles: HashMap<u32, Properties>,
+}
+
+#[derive(Debug, Default)]
+struct TiledObject {
+    name: String,
+    kind: String,
+    x: f32,
+    y: f32,
+    width: f32,
+    height: f32,
+    gid: Option<u32>,
+    properties: Properties,
+}
+
+//Files Tiled maps are saved as
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TiledFormat {
+    Tmj,
+    Tmx,
+}
+
+//Reads a .tmj or .tmx map and turns it into the same LevelData the ASCII format produces.
+//Tiles and objects either point at a legend entry through a "symbol" property or describe
+//their atlas index, collider, body and markers with their own properties. Objects can give
+//moving platforms a "path" of waypoint cells, switches and their targets a "channel", and an object of type PlayerStart places the player.
+//External tilesets have to be passed in by their source path, see external_files.
+pub fn parse(
+    source: &str,
+    format: TiledFormat,
+    legend: &TileLegend,
+    external: &HashMap<String, String>
+) -> Result<(TileLegend, LevelData), LevelError> {
+    let map = match format {
+        TiledFormat::Tmx => parse_tmx(source, external)?,
+        TiledFormat::Tmj => parse_tmj(source, external)?,
+    };
+
+    map.into_level(legend)
+}
+
+//Source paths of the external tilesets a map refers to, relative to the map
+pub fn external_files(source: &str, format: TiledFormat) -> Result<Vec<String>, LevelError> {
+    match format {
+        TiledFormat::Tmx => {
+            let document = roxmltree::Document::parse(source).map_err(|err| import_error(err.to_string()))?;
+            Ok(document
+                .root_element()
+                .children()
+                .filter(|node| node.has_tag_name("tileset"))
+                .filter_map(|tileset| tileset.attribute("source").map(str::to_string))
+                .collect())
+        }
+        TiledFormat::Tmj => {
+            let json: JsonMap = serde_json::from_str(source).map_err(|err| import_error(err.to_string()))?;
+            Ok(json.tilesets.into_iter().filter_map(|tileset| tileset.source).collect())
+        }
+    }
+}
+
+impl TiledMap {
+    fn into_level(self, base_legend: &TileLegend) -> Result<(TileLegend, LevelData), LevelError> {
+        let mut legend = base_legend.clone();
+        let mut grid = vec![vec![None; self.width]; self.height];
+        let mut platform_paths = HashMap::new();
+        let mut channels = HashMap::new();
+        let mut player_starts = Vec::new();
+
+        for layer in self.layers.iter() {
+            match layer {
+                TiledLayer::Tiles { name, data } => {
+                    for (i, gid) in data.iter().enumerate() {
+                        let gid = gid & !GID_FLAGS;
+                        if gid == 0 {
+                            continue;
+                        }
+                        if self.width == 0 {
+                            return Err(import_error(format!("layer '{}' has tiles but the map has no width", name)));
+                        }
+                        let (x, y) = (i % self.width, i / self.width);
+                        if y >= self.height {
+                            return Err(import_error(format!("layer '{}' has more tiles than the map", name)));
+                        }
+                        grid[y][x] = Some(self.tile_symbol(&mut legend, gid)?);
+                    }
+                }
+                TiledLayer::Objects { name, objects } => {
+                    for object in objects.iter() {
+                        if object.kind == PLAYER_START {
+                            player_starts.push(self.object_cell(object));
+                            continue;
+                        }
+
+                        let symbol = self.object_symbol(&mut legend, name, object)?;
+                        let cells = self.place_object(&mut grid, object, symbol);
+
+                        if let Some(path) = object.properties.get("path") {
+                            platform_paths.insert(self.object_cell(object), parse_path(path)?);
+                        }
+                        if let Some(channel) = object.properties.get("channel") {
+                            let channel: u32 = channel.parse().map_err(|_| {
+                                import_error(format!("object '{}' has an invalid channel '{}'", object.name, channel))
+                            })?;
+                            for cell in cells {
+                                channels.insert(cell, channel);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut level = LevelData::from_grid(grid)?;
+        level.platform_paths = platform_paths;
+        level.channels = channels;
+        for (x, y) in player_starts {
+            level.set_player_start(x, y)?;
+        }
+        level.take_player_start(&legend)?;
+        Ok((legend, level))
+    }
+
+    fn tile_symbol(&self, legend: &mut TileLegend, gid: u32) -> Result<char, LevelError> {
+        let tileset = self
+            .tilesets
+            .iter()
+            .filter(|tileset| tileset.first_gid <= gid)
+            .max_by_key(|tileset| tileset.first_gid)
+            .ok_or_else(|| import_error(format!("tile {} does not belong to any tileset", gid)))?;
+        let local_id = gid - tileset.first_gid;
+        let empty = Properties::new();
+        let properties = tileset.tiles.get(&local_id).unwrap_or(&empty);
+
+        if let Some(symbol) = properties.get("symbol") {
+            return legend_symbol(legend, symbol);
+        }
+
+        let symbol = char::from_u32(GENERATED_SYMBOL_START + gid)
+            .ok_or_else(|| import_error(format!("tile {} is out of range", gid)))?;
+        if legend.get(symbol).is_none() {
+            let def = tile_def_from_properties(properties, local_id as usize)?;
+            legend.tiles.insert(symbol, def);
+        }
+        Ok(symbol)
+    }
+
+    //Objects are matched by "symbol" property, then by a type naming a legend marker, then by their tile
+    fn object_symbol(&self, legend: &mut TileLegend, layer: &str, object: &TiledObject) -> Result<char, LevelError> {
+        if let Some(symbol) = object.properties.get("symbol") {
+            return legend_symbol(legend, symbol);
+        }
+
+        if !object.kind.is_empty() {
+            let marker: TileMarker = ron::from_str(&object.kind).map_err(|_| {
+                import_error(format!("object '{}' in layer '{}' has unknown type '{}'", object.name, layer, object.kind))
+            })?;
+            return legend.symbol_with_marker(marker).ok_or_else(|| {
+                import_error(format!("no legend entry has the {:?} marker used by object '{}'", marker, object.name))
+            });
+        }
+
+        match object.gid {
+            Some(gid) => self.tile_symbol(legend, gid & !GID_FLAGS),
+            None => Err(import_error(format!("object '{}' in layer '{}' has no type or symbol", object.name, layer))),
+        }
+    }
+
+    //Tile objects are anchored at their bottom-left corner, everything else at the top-left
+    fn object_cell(&self, object: &TiledObject) -> (usize, usize) {
+        let top = if object.gid.is_some() { object.y - object.height } else { object.y };
+        let column = (object.x / self.tile_width).floor().max(0.0) as usize;
+        let row = (top / self.tile_height).floor().max(0.0) as usize;
+        (column, row)
+    }
+
+    //Rectangles fill every cell along their top edge. Returns the cells the object covers
+    fn place_object(&self, grid: &mut [Vec<Option<char>>], object: &TiledObject, symbol: char) -> Vec<(usize, usize)> {
+        let (column, row) = self.object_cell(object);
+        let columns = ((object.width / self.tile_width).round() as usize).max(1);
+        let mut placed = Vec::new();
+
+        if let Some(cells) = grid.get_mut(row) {
+            for (x, cell) in cells.iter_mut().enumerate().skip(column).take(columns) {
+                *cell = Some(symbol);
+                placed.push((x, row));
+            }
+        }
+        placed
+    }
+}
+
+fn legend_symbol(legend: &TileLegend, symbol: &str) -> Result<char, LevelError> {
+    let mut chars = symbol.chars();
+    match (chars.next(), chars.next()) {
+        (Some(symbol), None) if legend.get(symbol).is_some() => Ok(symbol),
+        _ => Err(import_error(format!("symbol '{}' is not in the legend", symbol))),
+    }
+}
+
+//Waypoint cells written as "x,y;x,y"
+fn parse_path(path: &str) -> Result<Vec<(usize, usize)>, LevelError> {
+    path.split(';')
+        .map(|point| {
+            let mut coords = point.split(',').map(|coord| coord.trim().parse::<usize>());
+            match (coords.next(), coords.next(), coords.next()) {
+                (Some(Ok(x)), Some(Ok(y)), None) => Ok((x, y)),
+                _ => Err(import_error(format!("invalid path point '{}', expected x,y", point))),
+            }
+        })
+        .collect()
+}
+
+//Collider points relative to the tile's center, written as "x,y;x,y"
+fn parse_points(points: &str) -> Result<Vec<(f32, f32)>, LevelError> {
+    points
+        .split(';')
+        .map(|point| {
+            let mut coords = point.split(',').map(|coord| coord.trim().parse::<f32>());
+            match (coords.next(), coords.next(), coords.next()) {
+                (Some(Ok(x)), Some(Ok(y)), None) => Ok((x, y)),
+                _ => Err(import_error(format!("invalid collider point '{}', expected x,y", point))),
+            }
+        })
+        .collect()
+}
+
+fn tile_def_from_properties(properties: &Properties, local_id: usize) -> Result<TileDef, LevelError> {
+    let number = |name: &str| -> Result<f32, LevelError> {
+        properties
+            .get(name)
+            .ok_or_else(|| import_error(format!("tile {} is missing the '{}' property", local_id, name)))?
+            .parse()
+            .map_err(|_| import_error(format!("tile {} has a non-numeric '{}' property", local_id, name)))
+    };
+
+    let points = || -> Result<Vec<(f32, f32)>, LevelError> {
+        parse_points(
+            properties
+                .get("points")
+                .ok_or_else(|| import_error(format!("tile {} is missing the 'points' property", local_id)))?,
+        )
+    };
+
+    let atlas_index = match properties.get("atlas_index") {
+        Some(index) => index
+            .parse()
+            .map_err(|_| import_error(format!("tile {} has an invalid atlas_index", local_id)))?,
+        None => local_id,
+    };
+
+    let collider = match properties.get("collider").map(|shape| shape.as_str()) {
+        Some("cuboid") => Some(ColliderShape::Cuboid {
+            half_width: number("half_width")?,
+            half_height: number("half_height")?,
+        }),
+        Some("ball") => Some(ColliderShape::Ball { radius: number("radius")? }),
+        Some("triangle") => match points()?.as_slice() {
+            [a, b, c] => Some(ColliderShape::Triangle { points: [*a, *b, *c] }),
+            _ => return Err(import_error(format!("tile {} needs three points for a triangle collider", local_id))),
+        },
+        Some("polyline") => match points()? {
+            points if points.len() >= 2 => Some(ColliderShape::Polyline { points }),
+            _ => return Err(import_error(format!("tile {} needs at least two points for a polyline collider", local_id))),
+        },
+        Some(shape) => return Err(import_error(format!("tile {} has unknown collider '{}'", local_id, shape))),
+        None => None,
+    };
+
+    let body = match properties.get("body") {
+        Some(body) => Some(
+            ron::from_str::<TileBody>(body)
+                .map_err(|_| import_error(format!("tile {} has unknown body '{}'", local_id, body)))?,
+        ),
+        None => None,
+    };
+
+    let slope = match properties.get("slope") {
+        Some(_) => Some(number("slope")?),
+        None => None,
+    };
+
+    let value = match properties.get("value") {
+        Some(value) => Some(
+            value
+                .parse()
+                .map_err(|_| import_error(format!("tile {} has an invalid value", local_id)))?,
+        ),
+        None => None,
+    };
+
+    let damage = match properties.get("damage") {
+        Some(damage) => Some(
+            damage
+                .parse()
+                .map_err(|_| import_error(format!("tile {} has an invalid damage", local_id)))?,
+        ),
+        None => None,
+    };
+
+    let channel = match properties.get("channel") {
+        Some(channel) => Some(
+            channel
+                .parse()
+                .map_err(|_| import_error(format!("tile {} has an invalid channel", local_id)))?,
+        ),
+        None => None,
+    };
+
+    let material = if ["friction", "restitution", "conveyor", "bounce"].iter().any(|name| properties.contains_key(*name)) {
+        let defaults = TileMaterial::default();
+        let number_or = |name: &str, default: f32| match properties.get(name) {
+            Some(_) => number(name),
+            None => Ok(default),
+        };
+        Some(TileMaterial {
+            friction: number_or("friction", defaults.friction)?,
+            restitution: number_or("restitution", defaults.restitution)?,
+            conveyor: number_or("conveyor", defaults.conveyor)?,
+            bounce: match properties.get("bounce") {
+                Some(_) => Some(number("bounce")?),
+                None => None,
+            },
+        })
+    } else {
+        None
+    };
+
+    let mut markers = Vec::new();
+    if let Some(names) = properties.get("markers") {
+        for name in names.split(',').map(|name| name.trim()).filter(|name| !name.is_empty()) {
+            let marker = ron::from_str::<TileMarker>(name)
+                .map_err(|_| import_error(format!("tile {} has unknown marker '{}'", local_id, name)))?;
+            markers.push(marker);
+        }
+    }
+
+    Ok(TileDef {
+        name: None,
+        atlas_index: Some(atlas_index),
+        tile: None,
+        tileset: None,
+        collider,
+        body,
+        markers,
+        platform: None,
+        slope,
+        value,
+        channel,
+        autotile: None,
+        crumble: None,
+        beat: None,
+        hit_points: None,
+        drop: None,
+        damage,
+        material,
+    })
+}
+
+fn import_error(message: String) -> LevelError {
+    LevelError::Import(message)
+}
+
+//.tmj and .tsj files
+#[derive(Deserialize)]
+struct JsonMap {
+    width: usize,
+    height: usize,
+    tilewidth: f32,
+    tileheight: f32,
+    #[serde(default)]
+    orientation: String,
+    #[serde(default)]
+    infinite: bool,
+    #[serde(default)]
+    layers: Vec<JsonLayer>,
+    #[serde(default)]
+    tilesets: Vec<JsonTilesetRef>,
+}
+
+#[derive(Deserialize)]
+struct JsonLayer {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    data: Option<Value>,
+    #[serde(default)]
+    encoding: Option<String>,
+    #[serde(default)]
+    objects: Vec<JsonObject>,
+    #[serde(default)]
+    layers: Vec<JsonLayer>,
+}
+
+#[derive(Deserialize)]
+struct JsonObject {
+    #[serde(default)]
+    name: String,
+    #[serde(default, rename = "type")]
+    kind: String,
+    #[serde(default)]
+    class: String,
+    x: f32,
+    y: f32,
+    #[serde(default)]
+    width: f32,
+    #[serde(default)]
+    height: f32,
+    #[serde(default)]
+    gid: Option<u32>,
+    #[serde(default)]
+    properties: Vec<JsonProperty>,
+}
+
+#[derive(Deserialize)]
+struct JsonTilesetRef {
+    firstgid: u32,
+    #[serde(default)]
+    source: Option<String>,
+    #[serde(default)]
+    tiles: Vec<JsonTile>,
+}
+
+#[derive(Deserialize)]
+struct JsonTileset {
+    #[serde(default)]
+    tiles: Vec<JsonTile>,
+}
+
+#[derive(Deserialize)]
+struct JsonTile {
+    id: u32,
+    #[serde(default)]
+    properties: Vec<JsonProperty>,
+}
+
+#[derive(Deserialize)]
+struct JsonProperty {
+    name: String,
+    value: Value,
+}
+
+fn parse_tmj(source: &str, external: &HashMap<String, String>) -> Result<TiledMap, LevelError> {
+    let json: JsonMap = serde_json::from_str(source).map_err(|err| import_error(err.to_string()))?;
+    check_map_layout(&json.orientation, json.infinite)?;
+
+    let mut map = TiledMap {
+        width: json.width,
+        height: json.height,
+        tile_width: json.tilewidth,
+        tile_height: json.tileheight,
+        ..Default::default()
+    };
+
+    for tileset in json.tilesets.iter() {
+        let tiles = match &tileset.source {
+            Some(source) => parse_external_tileset(source, external)?,
+            None => json_tile_properties(&tileset.tiles),
+        };
+        map.tilesets.push(TiledTileset { first_gid: tileset.firstgid, tiles });
+    }
+
+    push_json_layers(&mut map.layers, &json.layers)?;
+    Ok(map)
+}
+
+fn push_json_layers(layers: &mut Vec<TiledLayer>, json_layers: &[JsonLayer]) -> Result<(), LevelError> {
+    for layer in json_layers.iter() {
+        match layer.kind.as_str() {
+            "tilelayer" => {
+                if layer.encoding.as_deref().unwrap_or("csv") != "csv" {
+                    return Err(import_error(format!("layer '{}' must be saved with CSV encoding", layer.name)));
+                }
+                let data = match &layer.data {
+                    Some(Value::Array(values)) => values.iter().map(|gid| gid.as_u64().unwrap_or(0) as u32).collect(),
+                    _ => Vec::new(),
+                };
+                layers.push(TiledLayer::Tiles { name: layer.name.clone(), data });
+            }
+            "objectgroup" => {
+                let objects = layer
+                    .objects
+                    .iter()
+                    .map(|object| TiledObject {
+                        name: object.name.clone(),
+                        kind: if object.kind.is_empty() { object.class.clone() } else { object.kind.clone() },
+                        x: object.x,
+                        y: object.y,
+                        width: object.width,
+                        height: object.height,
+                        gid: object.gid,
+                        properties: json_properties(&object.properties),
+                    })
+                    .collect();
+                layers.push(TiledLayer::Objects { name: layer.name.clone(), objects });
+            }
+            "group" => push_json_layers(layers, &layer.layers)?,
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn json_tile_properties(tiles: &[JsonTile]) -> HashMap<u32, Properties> {
+    tiles
+        .iter()
+        .map(|tile| (tile.id, json_properties(&tile.properties)))
+        .collect()
+}
+
+fn json_properties(properties: &[JsonProperty]) -> Properties {
+    properties
+        .iter()
+        .map(|property| {
+            let value = match &property.value {
+                Value::String(value) => value.clone(),
+                value => value.to_string(),
+            };
+            (property.name.clone(), value)
+        })
+        .collect()
+}
+
+//.tmx and .tsx files
+fn parse_tmx(source: &str, external: &HashMap<String, String>) -> Result<TiledMap, LevelError> {
+    let document = roxmltree::Document::parse(source).map_err(|err| import_error(err.to_string()))?;
+    let root = document.root_element();
+    check_map_layout(root.attribute("orientation").unwrap_or(""), root.attribute("infinite") == Some("1"))?;
+
+    let mut map = TiledMap {
+        width: xml_number(root, "width")?,
+        height: xml_number(root, "height")?,
+        tile_width: xml_number(root, "tilewidth")?,
+        tile_height: xml_number(root, "tileheight")?,
+        ..Default::default()
+    };
+
+    for tileset in root.children().filter(|node| node.has_tag_name("tileset")) {
+        let tiles = match tileset.attribute("source") {
+            Some(source) => parse_external_tileset(source, external)?,
+            None => xml_tile_properties(tileset),
+        };
+        map.tilesets.push(TiledTileset { first_gid: xml_number(tileset, "firstgid")?, tiles });
+    }
+
+    push_xml_layers(&mut map.layers, root)?;
+    Ok(map)
+}
+
+fn push_xml_layers(layers: &mut Vec<TiledLayer>, parent: roxmltree::Node) -> Result<(), LevelError> {
+    for node in parent.children().filter(|node| node.is_element()) {
+        let name = node.attribute("name").unwrap_or("").to_string();
+
+        match node.tag_name().name() {
+            "layer" => {
+                let data = match node.children().find(|child| child.has_tag_name("data")) {
+                    Some(data) => data,
+                    None => continue,
+                };
+                let gids = match data.attribute("encoding") {
+                    Some("csv") => data
+                        .text()
+                        .unwrap_or("")
+                        .split(',')
+                        .map(|gid| gid.trim().parse().unwrap_or(0))
+                        .collect(),
+                    None => data
+                        .children()
+                        .filter(|child| child.has_tag_name("tile"))
+                        .map(|tile| tile.attribute("gid").and_then(|gid| gid.parse().ok()).unwrap_or(0))
+                        .collect(),
+                    Some(_) => return Err(import_error(format!("layer '{}' must be saved with CSV encoding", name))),
+                };
+                layers.push(TiledLayer::Tiles { name, data: gids });
+            }
+            "objectgroup" => {
+                let mut objects = Vec::new();
+                for object in node.children().filter(|child| child.has_tag_name("object")) {
+                    objects.push(TiledObject {
+                        name: object.attribute("name").unwrap_or("").to_string(),
+                        kind: object.attribute("type").or_else(|| object.attribute("class")).unwrap_or("").to_string(),
+                        x: xml_number(object, "x")?,
+                        y: xml_number(object, "y")?,
+                        width: xml_number(object, "width").unwrap_or(0.0),
+                        height: xml_number(object, "height").unwrap_or(0.0),
+                        gid: object.attribute("gid").and_then(|gid| gid.parse().ok()),
+                        properties: xml_properties(object),
+                    });
+                }
+                layers.push(TiledLayer::Objects { name, objects });
+            }
+            "group" => push_xml_layers(layers, node)?,
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn xml_tile_properties(tileset: roxmltree::Node) -> HashMap<u32, Properties> {
+    tileset
+        .children()
+        .filter(|node| node.has_tag_name("tile"))
+        .filter_map(|tile| {
+            let id = tile.attribute("id")?.parse().ok()?;
+            Some((id, xml_properties(tile)))
+        })
+        .collect()
+}
+
+fn xml_properties(node: roxmltree::Node) -> Properties {
+    node.children()
+        .filter(|child| child.has_tag_name("properties"))
+        .flat_map(|properties| properties.children().filter(|child| child.has_tag_name("property")))
+        .filter_map(|property| {
+            let value = property.attribute("value").or_else(|| property.text())?;
+            Some((property.attribute("name")?.to_string(), value.to_string()))
+        })
+        .collect()
+}
+
+fn xml_number<T: std::str::FromStr>(node: roxmltree::Node, attribute: &str) -> Result<T, LevelError> {
+    node.attribute(attribute)
+        .and_then(|value| value.parse().ok())
+        .ok_or_else(|| import_error(format!("<{}> is missing a valid '{}' attribute", node.tag_name().name(), attribute)))
+}
+
+fn parse_external_tileset(source_path: &str, external: &HashMap<String, String>) -> Result<HashMap<u32, Properties>, LevelError> {
+    let source = external
+        .get(source_path)
+        .ok_or_else(|| LevelError::Io(format!("tileset {} was not loaded", source_path)))?;
+
+    if source_path.ends_with(".tsx") {
+        let document = roxmltree::Document::parse(source).map_err(|err| import_error(err.to_string()))?;
+        Ok(xml_tile_properties(document.root_element()))
+    } else {
+        let tileset: JsonTileset = serde_json::from_str(source).map_err(|err| import_error(err.to_string()))?;
+        Ok(json_tile_properties(&tileset.tiles))
+    }
+}
+
+fn check_map_layout(orientation: &str, infinite: bool) -> Result<(), LevelError> {
+    if orientation != "orthogonal" {
+        return Err(import_error(format!("only orthogonal maps are supported, found '{}'", orientation)));
+    }
+    if infinite {
+        return Err(import_error("infinite maps are not supported".to_string()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn harbor() -> (TileLegend, LevelData) {
+        let legend = TileLegend::parse(include_str!("../assets/default.legend.ron")).unwrap();
+        parse(include_str!("../assets/harbor.tmj"), TiledFormat::Tmj, &legend, &HashMap::new()).unwrap()
+    }
+
+    fn has_marker(legend: &TileLegend, level: &LevelData, x: usize, y: usize, marker: TileMarker) -> bool {
+        level
+            .get(x, y)
+            .and_then(|symbol| legend.get(symbol))
+            .is_some_and(|def| def.markers.contains(&marker))
+    }
+
+    fn properties(pairs: &[(&str, &str)]) -> Properties {
+        pairs.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect()
+    }
+
+    #[test]
+    fn reads_triangle_and_polyline_colliders() {
+        let triangle = properties(&[("collider", "triangle"), ("points", "-0.05,-0.04; 0.05,0.06; 0.05,-0.04")]);
+        assert_eq!(
+            tile_def_from_properties(&triangle, 0).unwrap().collider,
+            Some(ColliderShape::Triangle { points: [(-0.05, -0.04), (0.05, 0.06), (0.05, -0.04)] })
+        );
+
+        let polyline = properties(&[("collider", "polyline"), ("points", "-0.05,0.01;0.05,0.06")]);
+        assert_eq!(
+            tile_def_from_properties(&polyline, 0).unwrap().collider,
+            Some(ColliderShape::Polyline { points: vec![(-0.05, 0.01), (0.05, 0.06)] })
+        );
+    }
+
+    #[test]
+    fn rejects_colliders_with_the_wrong_points() {
+        let quad = properties(&[("collider", "triangle"), ("points", "0,0;1,0;1,1;0,1")]);
+        let single = properties(&[("collider", "polyline"), ("points", "0,0")]);
+        let garbled = properties(&[("collider", "polyline"), ("points", "0,0;one,1")]);
+        let missing = properties(&[("collider", "triangle")]);
+
+        for properties in [quad, single, garbled, missing] {
+            assert!(matches!(tile_def_from_properties(&properties, 7), Err(LevelError::Import(_))));
+        }
+    }
+
+    #[test]
+    fn reads_tiles_through_symbol_properties() {
+        let (_, level) = harbor();
+
+        assert_eq!((level.width, level.height), (60, 12));
+        assert_eq!(level.get(0, 0), Some('#'));
+        assert_eq!(level.get(0, 1), Some('|'));
+        assert_eq!(level.get(57, 10), Some('>'));
+        assert_eq!(level.get(24, 11), None);
+    }
+
+    #[test]
+    fn describes_tiles_from_their_own_properties() {
+        let (legend, level) = harbor();
+        let def = level.get(34, 7).and_then(|symbol| legend.get(symbol)).unwrap();
+
+        assert_eq!(def.atlas_index, Some(3));
+        assert_eq!(def.body, Some(TileBody::Fixed));
+        assert!(def.markers.contains(&TileMarker::TileCollider));
+    }
+
+    #[test]
+    fn places_objects_and_the_player_start() {
+        let (legend, level) = harbor();
+
+        assert_eq!(level.player_start, Some((2, 9)));
+        assert!(has_marker(&legend, &level, 20, 6, TileMarker::MovingCollider));
+        assert!(has_marker(&legend, &level, 40, 10, TileMarker::EncounterSpawner));
+    }
+}
@@ -1,16 +1,24 @@
-use std::{fs::File, io::{BufReader, BufRead}};
+use std::{collections::{HashMap, HashSet}, marker::PhantomData, path::PathBuf};
 
-use bevy::prelude::*;
+use bevy::{prelude::*, asset::LoadState, ecs::system::SystemParam};
 
-use bevy_rapier2d::{prelude::{Collider, RigidBody, Velocity, GravityScale, Ccd, Sleeping, LockedAxes}};
+use bevy_rapier2d::{prelude::*, rapier::math::Vector};
 
-use crate::{spriteloader::{AsciiSheet, spawn_sprite}, TILE_SIZE, GameState};
+use crate::{autotile::tile_atlas_index, destructible::{Destructible, TileBroken}, spriteloader::spawn_sprite, chunk::{ChunkQuad, ChunkRange, chunk_bounds, chunk_coord, cull_chunks, spawn_tile_chunk, STREAM_IN_MARGIN, STREAM_OUT_MARGIN}, legend::{BeatPattern, ColliderShape, CrumbleTiming, PathMode, PlatformPath, TileDef, TileLegend, TileMarker, insert_channel_components, insert_tile_components}, level::{LevelData, LevelRegistry, TileRect, REGISTRY_PATH}, level_asset::{LevelAsset, LevelAssetLoader, LevelFile, LevelFileLoader, LevelRegistryLoader}, player::{standing_on, wall_collision_check, DroppingThrough, Player, PLAYER_START}, tileset::{Tileset, Tilesets}, TILE_SIZE, PIXELS_PER_METER, GameState};
 
 pub struct TileMapPlugin;
 
+const BEAT_SECONDS: f32 = 1.0;
+//Opacity a crumbling tile fades down to before it is gone
+const CRUMBLE_MIN_ALPHA: f32 = 0.4;
+
 #[derive(Component)]
 struct Map;
 
+//Hidden by hide_map rather than by the tile's own state
+#[derive(Component)]
+struct HiddenWithMap;
+
 #[derive(Component)]
 pub struct EncounterSpawner;
 
@@ -20,16 +28,380 @@ pub struct TileCollider;
 #[derive(Component)]
 pub struct WallCollider;
 
+//Ladders and climbable walls
 #[derive(Component)]
+pub struct Ladder;
+
+//Walkable incline, gradient is rise per run and positive when it rises to the right
+#[derive(Component)]
+pub struct Slope {
+    pub gradient: f32,
+}
+
+//Only collides with bodies landing on it from above
+#[derive(Component)]
+pub struct OneWayPlatform;
+
+//Belt surface that carries whatever touches it along, speed is positive to the right
+#[derive(Component)]
+pub struct Conveyor {
+    pub speed: f32,
+}
+
+//Launches the player upwards on landing
+#[derive(Component)]
+pub struct BouncePad {
+    pub speed: f32,
+}
+
+//Components the contact hooks look at, on either collider of a pair
+pub type TileHookData<'a> = (Option<&'a OneWayPlatform>, Option<&'a DroppingThrough>, Option<&'a Conveyor>);
+
+pub struct TileContactHooks;
+
+//Platform travelling along a PlatformPath. Waypoint 0 is the cell it was spawned in
+#[derive(Component, Clone)]
 pub struct MovingCollider {
-    movements_right: i32,
-    movements_left: i32,
-    timer: Timer
+    path: PlatformPath,
+    //Taken from the Transform the first time the platform moves
+    origin: Option<Vec3>,
+    from: usize,
+    to: usize,
+    forward: bool,
+    //Linear progress from one waypoint to the next, 0..=1
+    progress: f32,
+    wait: f32,
+    stopped: bool,
+    //Linked platforms only move while their channel is on
+    powered: bool,
+    //How far the platform moved this frame, so riders can move with it
+    displacement: Vec3,
+}
+
+impl MovingCollider {
+    pub fn new(path: PlatformPath) -> Self {
+        MovingCollider {
+            wait: path.delay,
+            path,
+            origin: None,
+            from: 0,
+            to: 1,
+            forward: true,
+            progress: 0.0,
+            stopped: false,
+            powered: true,
+            displacement: Vec3::ZERO,
+        }
+    }
+
+    pub fn displacement(&self) -> Vec3 {
+        self.displacement
+    }
+
+    fn point(&self, origin: Vec3, index: usize) -> Vec3 {
+        match index.checked_sub(1).and_then(|index| self.path.waypoints.get(index)) {
+            Some(&(x, y)) => origin + Vec3::new(x as f32 * TILE_SIZE, -(y as f32) * TILE_SIZE, 0.0),
+            None => origin,
+        }
+    }
+
+    //Waypoint to head for after arriving at the current one, if any
+    fn next_waypoint(&mut self) -> Option<usize> {
+        let last = self.path.waypoints.len();
+
+        match self.path.mode {
+            PathMode::Loop => Some(if self.to == last { 0 } else { self.to + 1 }),
+            PathMode::Once => if self.to < last { Some(self.to + 1) } else { None },
+            PathMode::PingPong => {
+                if self.forward && self.to == last || !self.forward && self.to == 0 {
+                    self.forward = !self.forward;
+                }
+                Some(if self.forward { self.to + 1 } else { self.to - 1 })
+            }
+        }
+    }
+
+    //Moves delta seconds along the path and returns where the platform is now
+    fn advance(&mut self, origin: Vec3, mut delta: f32) -> Option<Vec3> {
+        if self.stopped || self.path.waypoints.is_empty() || self.path.speed <= 0.0 {
+            return None;
+        }
+
+        if self.wait > 0.0 {
+            let waited = self.wait.min(delta);
+            self.wait -= waited;
+            delta -= waited;
+        }
+
+        let (from, to) = (self.point(origin, self.from), self.point(origin, self.to));
+        let duration = from.distance(to) / (self.path.speed * TILE_SIZE);
+        self.progress = if duration > 0.0 { self.progress + delta / duration } else { 1.0 };
+
+        if self.progress < 1.0 {
+            return Some(from.lerp(to, self.path.easing.apply(self.progress)));
+        }
+
+        self.progress = 0.0;
+        self.wait = self.path.pause;
+        self.from = self.to;
+        match self.next_waypoint() {
+            Some(next) => self.to = next,
+            None => self.stopped = true,
+        }
+        Some(to)
+    }
+}
+
+//Grid cell a dynamic tile was spawned from
+#[derive(Component)]
+pub struct TileCell {
+    pub x: usize,
+    pub y: usize,
+}
+
+//Level that is currently being streamed in around the camera
+pub struct LoadedLevel {
+    //Index into the LevelRegistry, which CurrentLevel moves past as soon as the next level is requested
+    pub index: usize,
+    pub legend: TileLegend,
+    pub level: LevelData,
+    //Every tileset the legend draws from, keyed by path
+    tilesets: HashMap<String, Handle<Tileset>>,
+    //Rectangles of mergeable tiles, worked out for the whole level so colliders don't end at chunk edges
+    merged: Vec<(char, TileRect)>,
+}
+
+//Spawned chunk roots, and what has to outlast them being streamed out
+#[derive(Default)]
+pub struct StreamedChunks {
+    spawned: HashMap<(usize, usize), Entity>,
+    //Moving platforms by the cell they started in. They are added to the Map instead of a chunk root,
+    //so they keep moving and stay in sight while that chunk is streamed out
+    platforms: HashMap<(usize, usize), Entity>,
+    //Cells whose tile is gone for good or has been swapped for another symbol
+    replaced_cells: HashMap<(usize, usize), Option<char>>,
+    //Spawned merged colliders by index into LoadedLevel::merged
+    merged_colliders: HashMap<usize, Entity>,
+    //Hit points left on destructible tiles whose chunk has been streamed out
+    saved_hit_points: HashMap<(usize, usize), u32>,
+    //Crumbling tiles that had started crumbling when their chunk was streamed out, their timers pause meanwhile
+    saved_crumbling: HashMap<(usize, usize), Crumbling>,
+    //Whether Timed tiles were solid when their chunk was streamed out
+    saved_timed: HashMap<(usize, usize), bool>,
+}
+
+impl StreamedChunks {
+    //Keeps the tile at this cell from being spawned again when its chunk is streamed back in
+    pub fn clear_cell(&mut self, x: usize, y: usize) {
+        self.replaced_cells.insert((x, y), None);
+    }
+
+    //Spawns symbol instead of the level's tile when the cell's chunk is streamed back in
+    pub fn replace_cell(&mut self, x: usize, y: usize, symbol: char) {
+        self.replaced_cells.insert((x, y), Some(symbol));
+    }
+}
+
+//Assets of the campaign and of the level that is being played
+#[derive(Default)]
+pub struct LevelHandles {
+    registry: Handle<LevelRegistry>,
+    level: Handle<LevelAsset>,
+    //Legend and files the level refers to, keyed by asset path, loaded so their changes rebuild the level
+    files: HashMap<PathBuf, Handle<LevelFile>>,
+    //Set from requesting a level until it is spawned, reloads of a spawned level leave the player where it is
+    pending: bool,
+}
+
+//Index into the LevelRegistry of the level that is currently spawned
+#[derive(Default)]
+pub struct CurrentLevel(pub usize);
+
+//Where the player is placed when the current level is loaded
+pub struct LevelStart(pub Vec3);
+
+pub struct LevelCompleted {
+    pub index: usize,
+}
+
+pub struct LevelLoadFailed {
+    pub index: usize,
+    pub path: String,
+}
+
+#[derive(Component)]
+pub struct LevelGoal;
+
+//Touching one of these makes it the player's respawn point
+#[derive(Component)]
+pub struct Checkpoint;
+
+//Where the player respawns after dying, the level start until a checkpoint is touched
+#[derive(Default)]
+pub struct ActiveCheckpoint(pub Option<Vec3>);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SwitchKind {
+    //On while the player stands on it
+    PressurePlate,
+    //Flipped with E while touching it, and stays that way
+    Lever,
+}
+
+//Turns its channel on, operating every SwitchTarget on the same channel
+#[derive(Component)]
+pub struct Switch {
+    pub channel: u32,
+    pub kind: SwitchKind,
+}
+
+//Doors open and platforms move only while their channel is on
+#[derive(Component)]
+pub struct SwitchTarget {
+    pub channel: u32,
+}
+
+//What a channel operates, with a tracker so targets spawned by a streamed in chunk catch up with their channel
+type SwitchTargetData<'a> = (
+    Entity,
+    &'a SwitchTarget,
+    ChangeTrackers<SwitchTarget>,
+    Option<&'a mut Door>,
+    Option<&'a mut MovingCollider>,
+    Option<&'a mut Visibility>,
+);
+
+//Loses its collider and sprite while open
+#[derive(Component)]
+pub struct Door {
+    shape: Option<ColliderShape>,
+    open: bool,
+}
+
+impl Door {
+    pub fn new(shape: Option<ColliderShape>) -> Self {
+        Door { shape, open: false }
+    }
+}
+
+//Gives way a moment after the player lands on it and comes back later
+#[derive(Component, Clone)]
+pub struct Crumbling {
+    shape: Option<ColliderShape>,
+    timing: CrumbleTiming,
+    state: CrumbleState,
+    timer: Timer,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CrumbleState {
+    Intact,
+    Crumbling,
+    Gone,
+}
+
+impl Crumbling {
+    pub fn new(shape: Option<ColliderShape>, timing: CrumbleTiming) -> Self {
+        Crumbling { shape, timing, state: CrumbleState::Intact, timer: Timer::default() }
+    }
+}
+
+type CrumblingTileData<'a> = (
+    Entity,
+    &'a mut Crumbling,
+    &'a Transform,
+    Option<&'a mut Visibility>,
+    Option<&'a mut TextureAtlasSprite>,
+);
+
+//What a tile changed about itself, saved when its chunk is streamed out
+type TileStateData<'a> = (&'a TileCell, Option<&'a Destructible>, Option<&'a Crumbling>, Option<&'a TimedBlock>);
+
+//Appears and disappears on the beat
+#[derive(Component)]
+pub struct TimedBlock {
+    shape: Option<ColliderShape>,
+    pattern: BeatPattern,
+    solid: bool,
+}
+
+impl TimedBlock {
+    pub fn new(shape: Option<ColliderShape>, pattern: BeatPattern) -> Self {
+        TimedBlock { shape, pattern, solid: true }
+    }
+}
+
+//Global beat every Timed tile follows, restarted with every level
+pub struct BeatClock {
+    timer: Timer,
+    beat: u32,
+}
+
+impl Default for BeatClock {
+    fn default() -> Self {
+        BeatClock { timer: Timer::from_seconds(BEAT_SECONDS, true), beat: 0 }
+    }
+}
+
+//Channels that are on, and the levers holding them on by cell so they survive chunks being streamed out
+#[derive(Default)]
+pub struct SwitchChannels {
+    active: HashSet<u32>,
+    levers: HashMap<(usize, usize), u32>,
+}
+
+impl SwitchChannels {
+    pub fn is_active(&self, channel: u32) -> bool {
+        self.active.contains(&channel)
+    }
+}
+
+//Sent whenever a channel turns on or off, operating the SwitchTargets on it
+pub struct SwitchToggled {
+    pub channel: u32,
+    pub active: bool,
+}
+
+//The Map and the tiles of its chunk roots, looked at when chunks are streamed in and out
+#[derive(SystemParam)]
+struct ChunkTiles<'w, 's> {
+    map_query: Query<'w, 's, Entity, With<Map>>,
+    children_query: Query<'w, 's, &'static Children>,
+    tile_state_query: Query<'w, 's, TileStateData<'static>>,
+}
+
+//Everything needed to turn a level into sprites and chunk meshes
+#[derive(SystemParam)]
+struct TileAssets<'w, 's> {
+    tilesets: ResMut<'w, Tilesets>,
+    tileset_assets: Res<'w, Assets<Tileset>>,
+    atlases: Res<'w, Assets<TextureAtlas>>,
+    meshes: ResMut<'w, Assets<Mesh>>,
+    #[system_param(ignore)]
+    marker: PhantomData<&'s ()>,
 }
 
 impl Plugin for TileMapPlugin {
     fn build(&self, app: &mut App) {
-        app.add_startup_system(build_stage)
+        app.init_resource::<CurrentLevel>()
+        .init_resource::<ActiveCheckpoint>()
+        .init_resource::<SwitchChannels>()
+        .init_resource::<BeatClock>()
+        .insert_resource(PhysicsHooksWithQueryResource::<TileHookData<'static>>(Box::new(TileContactHooks)))
+        .init_resource::<LevelHandles>()
+        .add_asset::<LevelAsset>()
+        .add_asset::<LevelRegistry>()
+        .add_asset::<LevelFile>()
+        .init_asset_loader::<LevelAssetLoader>()
+        .init_asset_loader::<LevelFileLoader>()
+        .init_asset_loader::<LevelRegistryLoader>()
+        .add_event::<LevelCompleted>()
+        .add_event::<LevelLoadFailed>()
+        .add_event::<SwitchToggled>()
+        .add_startup_system(build_stage)
+        .add_system(start_campaign)
+        .add_system(spawn_loaded_level.label("rebuild_level"))
+        .add_system(report_failed_levels.after("rebuild_level"))
         .add_system_set(
             SystemSet::on_enter(GameState::Overworld)
                 .with_system(show_map)
@@ -37,158 +409,980 @@ impl Plugin for TileMapPlugin {
         )
         .add_system_set(
             SystemSet::on_update(GameState::Overworld)
-                .with_system(move_floating_platform)
+                .with_system(press_switches.label("press_switches"))
+                .with_system(operate_switch_targets.label("operate_switch_targets").after("press_switches"))
+                .with_system(crumble_tiles)
+                .with_system(toggle_timed_tiles)
+                .with_system(replace_broken_tiles)
+                .with_system(move_floating_platform.label("move_platforms").after("operate_switch_targets"))
+                .with_system(check_level_goal.label("check_level_goal"))
+                .with_system(activate_checkpoints)
+                .with_system(advance_level.after("check_level_goal"))
+                //Chunks are spawned before a rebuild despawns the Map they would be added to
+                .with_system(stream_chunks.before("rebuild_level"))
+                .with_system(cull_chunks)
         )
             .add_system_set(SystemSet::on_exit(GameState::Overworld).with_system(hide_map));
     }
 }
 
-//Builds stage using chars from textfile
-fn build_stage(mut commands: Commands, ascii: Res<AsciiSheet>) {
-    let file = File::open("assets/map.txt").expect("No map file found");
-    let mut tiles = Vec::new();
+//Starts loading the level registry, the first stage is built once it is in
+fn build_stage(asset_server: Res<AssetServer>, mut handles: ResMut<LevelHandles>) {
+    handles.registry = asset_server.load(REGISTRY_PATH);
+}
 
-    for (y, line) in BufReader::new(file).lines().enumerate() {
-        if let Ok(line) = line {
-            for (x, char) in line.chars().enumerate() {
-
-                let mut index = 0;
-
-                match char {
-                    '#' => index = 0,
-                    '#' => index = 1,
-                    '|'  => index = 2,
-                    '-' => index = 0,
-
-                    _ => index = 3,
-                };
-
-                if index != 3 {
-                    let tile = spawn_sprite(
-                        &mut commands,
-                        &ascii,
-                        index,
-                        Color::rgb(1.0, 1.0, 1.0),
-                        Vec3::new(x as f32 * TILE_SIZE, -(y as f32) * TILE_SIZE, 100.0)
-                    );
-                    if char == '*' {
-                        commands.entity(tile).insert(EncounterSpawner);
-                        
-                    }
-                    if char == '#' {
-                        commands.entity(tile).insert(TileCollider)
-                        .insert(RigidBody::Fixed)
-                        .insert(Collider::cuboid(0.08, 0.06));
-                    }
+//Requests the current level whenever the registry finishes loading or is changed
+fn start_campaign(
+    asset_server: Res<AssetServer>,
+    registries: Res<Assets<LevelRegistry>>,
+    current_level: Res<CurrentLevel>,
+    mut handles: ResMut<LevelHandles>,
+    mut asset_events: EventReader<AssetEvent<LevelRegistry>>
+) {
+    let loaded = asset_events.iter().any(|event| match event {
+        AssetEvent::Created { handle } | AssetEvent::Modified { handle } => *handle == handles.registry,
+        _ => false,
+    });
+    if !loaded {
+        return;
+    }
 
-                    if char == '-' {
-                        commands.entity(tile).insert(MovingCollider {
-                            movements_right: 0,
-                            movements_left: 0,
-                            timer: Timer::from_seconds(1.5, true),
-                        })
-                        .insert(TileCollider)
-                        .insert(RigidBody::KinematicPositionBased)
-                        .insert(Velocity {
-                            linvel: Vec2::new(0.0, 0.0),
-                            angvel: 0.0
-                        })
-                        .insert(GravityScale(0.1))
-                        .insert(Ccd::enabled())
-                        .insert(Sleeping::disabled())
-                        .insert(LockedAxes::ROTATION_LOCKED)
-                        .insert(Collider::cuboid(0.08, 0.02));
-                    }
+    if let Some(registry) = registries.get(&handles.registry) {
+        request_level(&asset_server, registry, current_level.0, &mut handles);
+    }
+}
 
-                    if char == '|' {
-                        commands.entity(tile).insert(WallCollider)
-                        .insert(Collider::cuboid(0.05, 0.10));
-                    }
-                    tiles.push(tile);
-                }
+//Starts loading the registry entry at index, it is spawned by spawn_loaded_level once it is in
+fn request_level(asset_server: &AssetServer, registry: &LevelRegistry, index: usize, handles: &mut LevelHandles) -> bool {
+    let entry = match registry.get(index) {
+        Some(entry) => entry,
+        None => {
+            error!("No level at index {} in the level registry", index);
+            return false;
+        }
+    };
+    info!("Loading level {}: {}", index + 1, entry.name);
+
+    handles.level = asset_server.load(entry.asset_path().as_str());
+    handles.pending = true;
+    true
+}
+
+//Spawns a requested level once it has loaded and rebuilds the Map in place when its file, its legend
+//or a file it refers to is saved
+#[allow(clippy::too_many_arguments)]
+fn spawn_loaded_level(
+    mut commands: Commands,
+    mut tile_assets: TileAssets,
+    asset_server: Res<AssetServer>,
+    levels: Res<Assets<LevelAsset>>,
+    level_files: Res<Assets<LevelFile>>,
+    current_level: Res<CurrentLevel>,
+    mut handles: ResMut<LevelHandles>,
+    mut asset_events: EventReader<AssetEvent<LevelAsset>>,
+    mut file_events: EventReader<AssetEvent<LevelFile>>,
+    mut failed_events: EventWriter<LevelLoadFailed>,
+    map_query: Query<Entity, With<Map>>
+) {
+    let modified = asset_events.iter().any(|event| match event {
+        AssetEvent::Modified { handle } => *handle == handles.level,
+        _ => false,
+    });
+    let file_modified = file_events.iter().any(|event| match event {
+        AssetEvent::Modified { handle } => handles.files.values().any(|file| file == handle),
+        _ => false,
+    });
+
+    if handles.pending && asset_server.get_load_state(&handles.level) == LoadState::Failed {
+        let path = asset_server
+            .get_handle_path(&handles.level)
+            .map(|path| path.path().display().to_string())
+            .unwrap_or_default();
+        failed_events.send(LevelLoadFailed { index: current_level.0, path });
+        handles.pending = false;
+        return;
+    }
+
+    if !handles.pending && !modified && !file_modified {
+        return;
+    }
+    let level_asset = match levels.get(&handles.level) {
+        Some(level_asset) => level_asset,
+        None => return,
+    };
+
+    let (legend, level) = if handles.pending || modified {
+        handles.files = level_asset
+            .dependencies()
+            .map(|path| (path.clone(), asset_server.load(path.as_path())))
+            .collect();
+        (level_asset.legend.clone(), level_asset.level.clone())
+    } else {
+        let files = handles
+            .files
+            .iter()
+            .filter_map(|(path, handle)| level_files.get(handle).map(|file| (path.clone(), file.0.clone())))
+            .collect();
+
+        //A broken edit keeps the current Map until the files are fixed
+        match level_asset.rebuild(&files) {
+            Ok(rebuilt) => rebuilt,
+            Err(err) => {
+                error!("Could not rebuild stage: {}", err);
+                return;
+            }
+        }
+    };
+
+    for map in map_query.iter() {
+        commands.entity(map).despawn_recursive();
+    }
+    let start = spawn_level(&mut commands, &mut tile_assets, &asset_server, current_level.0, legend, level);
+
+    if handles.pending {
+        commands.insert_resource(LevelStart(start));
+        commands.insert_resource(ActiveCheckpoint::default());
+        commands.insert_resource(SwitchChannels::default());
+        commands.insert_resource(BeatClock::default());
+        handles.pending = false;
+    }
+}
+
+impl ChunkTiles<'_, '_> {
+    //Remembers what the tiles of a chunk changed about themselves before the chunk is despawned
+    fn save_tile_state(&self, root: Entity, streamed: &mut StreamedChunks) {
+        let children = match self.children_query.get(root) {
+            Ok(children) => children,
+            Err(_) => return,
+        };
+
+        for (cell, destructible, crumbling, timed) in children.iter().filter_map(|child| self.tile_state_query.get(*child).ok()) {
+            let cell = (cell.x, cell.y);
+            if let Some(destructible) = destructible {
+                streamed.saved_hit_points.insert(cell, destructible.hit_points);
+            }
+            if let Some(crumbling) = crumbling.filter(|crumbling| crumbling.state != CrumbleState::Intact) {
+                streamed.saved_crumbling.insert(cell, crumbling.clone());
+            }
+            if let Some(timed) = timed {
+                streamed.saved_timed.insert(cell, timed.solid);
             }
         }
     }
+}
+
+impl TileAssets<'_, '_> {
+    //Chunks wait until every tileset of the level can be drawn
+    fn tilesets_ready(&self, loaded_level: &LoadedLevel) -> bool {
+        loaded_level.tilesets.values().all(|handle| {
+            self.tilesets.material(handle).is_some()
+                && self
+                    .tileset_assets
+                    .get(handle)
+                    .is_some_and(|tileset| self.atlases.get(&tileset.atlas).is_some())
+        })
+    }
+}
+
+//Spawns an empty Map for the level's chunks to stream into, returning where the player starts
+fn spawn_level(
+    commands: &mut Commands,
+    tile_assets: &mut TileAssets,
+    asset_server: &AssetServer,
+    index: usize,
+    legend: TileLegend,
+    level: LevelData
+) -> Vec3 {
+    let tilesets = legend
+        .tilesets()
+        .into_iter()
+        .map(|path| (path.to_string(), tile_assets.tilesets.request(asset_server, path)))
+        .collect();
+
+    let mut merged_symbols: Vec<char> = legend
+        .tiles
+        .iter()
+        .filter(|(_, def)| def.is_mergeable())
+        .map(|(symbol, _)| *symbol)
+        .collect();
+    merged_symbols.sort_unstable();
+    let merged = merged_symbols
+        .into_iter()
+        .flat_map(|symbol| level.merged_rects(symbol).into_iter().map(move |rect| (symbol, rect)))
+        .collect();
 
+    //Chunks are added under the Map as the camera approaches them
     commands
         .spawn()
         .insert(Map)
         .insert(Name::new("Map"))
         .insert(Transform::default())
+        .insert(GlobalTransform::default());
+
+    let start = match level.player_start {
+        Some((x, y)) => tile_translation(x, y).truncate().extend(PLAYER_START.z),
+        None => PLAYER_START,
+    };
+    commands.insert_resource(LoadedLevel {
+        index,
+        legend,
+        level,
+        tilesets,
+        merged,
+    });
+    commands.insert_resource(StreamedChunks::default());
+
+    start
+}
+
+//Spawns every tile of one chunk under a common root so the chunk can be despawned as a whole.
+//Moving platforms are added to the Map, once
+fn spawn_chunk(
+    commands: &mut Commands,
+    tile_assets: &mut TileAssets,
+    loaded_level: &LoadedLevel,
+    streamed: &mut StreamedChunks,
+    map: Entity,
+    coord: (usize, usize)
+) -> Entity {
+    let bounds = chunk_bounds(coord);
+    let legend = &loaded_level.legend;
+    let mut tiles = Vec::new();
+    let mut quads: HashMap<&str, Vec<ChunkQuad>> = HashMap::new();
+
+    for tile_data in loaded_level.level.tiles_in(bounds) {
+        //Cells emptied or changed while playing, like picked up collectibles and destroyed tiles
+        let symbol = match streamed.replaced_cells.get(&(tile_data.x, tile_data.y)) {
+            Some(Some(symbol)) => *symbol,
+            Some(None) => continue,
+            None => tile_data.symbol,
+        };
+        let def = match legend.get(symbol) {
+            Some(def) => def,
+            None => continue,
+        };
+        let translation = tile_translation(tile_data.x, tile_data.y);
+        let tileset_path = legend.tileset_for(def);
+        let (tileset, atlas_index) = tile_art(tile_assets, loaded_level, def, tile_data.x, tile_data.y);
+
+        //Static tiles are drawn by the chunk mesh and only get an entity if they carry components
+        if def.is_static() {
+            if let Some(index) = atlas_index {
+                quads.entry(tileset_path).or_default().push(ChunkQuad { translation, index });
+            }
+
+            //Mergeable tiles get their colliders from the level's merged rectangles, see stream_chunks.
+            //Drops put in their cell while playing are not part of those
+            if def.is_mergeable() && symbol == tile_data.symbol {
+                continue;
+            }
+
+            if def.collider.is_some() || def.body.is_some() || !def.markers.is_empty() {
+                let tile = commands
+                    .spawn()
+                    .insert(Transform::from_translation(translation))
+                    .insert(GlobalTransform::default())
+                    .id();
+                insert_tile_components(commands, tile, def);
+                tiles.push(tile);
+            }
+            continue;
+        }
+
+        if def.markers.contains(&TileMarker::MovingCollider) {
+            if !streamed.platforms.contains_key(&(tile_data.x, tile_data.y)) {
+                let platform = spawn_dynamic_tile(commands, loaded_level, streamed, def, tileset, atlas_index, tile_data.x, tile_data.y);
+                commands.entity(map).add_child(platform);
+                streamed.platforms.insert((tile_data.x, tile_data.y), platform);
+            }
+            continue;
+        }
+
+        tiles.push(spawn_dynamic_tile(commands, loaded_level, streamed, def, tileset, atlas_index, tile_data.x, tile_data.y));
+    }
+
+    //One mesh per tileset
+    for (tileset_path, quads) in quads {
+        let handle = &loaded_level.tilesets[tileset_path];
+        let tileset = tile_assets.tileset_assets.get(handle);
+        let atlas = tileset.and_then(|tileset| tile_assets.atlases.get(&tileset.atlas));
+        if let (Some(atlas), Some(material)) = (atlas, tile_assets.tilesets.material(handle)) {
+            if let Some(mesh) = spawn_tile_chunk(commands, &mut tile_assets.meshes, atlas, material.clone(), &quads) {
+                tiles.push(mesh);
+            }
+        }
+    }
+
+    commands
+        .spawn()
+        .insert(Name::new(format!("Chunk {}, {}", coord.0, coord.1)))
+        .insert(Transform::default())
         .insert(GlobalTransform::default())
-        .push_children(&tiles);
+        .push_children(&tiles)
+        .id()
 }
 
-//Movement for horizontal moving platforms
-fn move_floating_platform(
+//Tileset a tile is drawn from and its index in the tileset's atlas
+fn tile_art<'a>(
+    tile_assets: &'a TileAssets,
+    loaded_level: &LoadedLevel,
+    def: &TileDef,
+    x: usize,
+    y: usize
+) -> (Option<&'a Tileset>, Option<usize>) {
+    let tileset = loaded_level
+        .tilesets
+        .get(loaded_level.legend.tileset_for(def))
+        .and_then(|handle| tile_assets.tileset_assets.get(handle));
+    let atlas_index = tile_atlas_index(def, tileset, &loaded_level.level, x, y).or_else(|| {
+        def.tile.as_deref().and_then(|name| tileset.and_then(|tileset| tileset.index(name)))
+    });
+    (tileset, atlas_index)
+}
+
+//Spawns a tile that moves, changes or can disappear as its own sprite entity, applying what the level
+//editor and earlier streaming changed about its cell
+#[allow(clippy::too_many_arguments)]
+fn spawn_dynamic_tile(
+    commands: &mut Commands,
+    loaded_level: &LoadedLevel,
+    streamed: &mut StreamedChunks,
+    def: &TileDef,
+    tileset: Option<&Tileset>,
+    atlas_index: Option<usize>,
+    x: usize,
+    y: usize
+) -> Entity {
+    let translation = tile_translation(x, y);
+    let tile = match (atlas_index, tileset) {
+        (Some(index), Some(tileset)) => spawn_sprite(
+            commands,
+            &tileset.atlas,
+            index,
+            Color::rgb(1.0, 1.0, 1.0),
+            translation
+        ),
+        _ => commands
+            .spawn()
+            .insert(Transform::from_translation(translation))
+            .insert(GlobalTransform::default())
+            .id(),
+    };
+
+    insert_tile_components(commands, tile, def);
+    commands.entity(tile).insert(TileCell { x, y });
+
+    //Paths placed in the level editor replace the waypoints of the legend entry
+    if let Some(cells) = loaded_level.level.platform_paths.get(&(x, y)) {
+        if def.markers.contains(&TileMarker::MovingCollider) {
+            let waypoints = cells
+                .iter()
+                .map(|&(path_x, path_y)| (path_x as i32 - x as i32, path_y as i32 - y as i32))
+                .skip_while(|offset| *offset == (0, 0))
+                .collect();
+            let path = PlatformPath { waypoints, ..def.platform.clone().unwrap_or_default() };
+            commands.entity(tile).insert(MovingCollider::new(path));
+        }
+    }
+
+    //Channels placed in the level editor replace the one of the legend entry
+    if let Some(channel) = loaded_level.level.channels.get(&(x, y)) {
+        insert_channel_components(commands, tile, def, *channel);
+    }
+
+    //Destructible tiles keep the damage they took before their chunk was streamed out
+    if let Some(hit_points) = streamed.saved_hit_points.remove(&(x, y)) {
+        if def.markers.contains(&TileMarker::Destructible) {
+            commands.entity(tile).insert(Destructible { hit_points, drop: def.drop });
+        }
+    }
+
+    //Crumbling and Timed tiles pick up where they were, without their collider and sprite while gone
+    if let Some(crumbling) = streamed.saved_crumbling.remove(&(x, y)) {
+        if def.markers.contains(&TileMarker::Crumbling) {
+            let gone = crumbling.state == CrumbleState::Gone;
+            commands.entity(tile).insert(crumbling);
+            if gone {
+                spawn_tile_gone(commands, tile);
+            }
+        }
+    }
+    if let Some(solid) = streamed.saved_timed.remove(&(x, y)) {
+        if def.markers.contains(&TileMarker::Timed) {
+            let mut block = TimedBlock::new(def.collider.clone(), def.beat.clone().unwrap_or_default());
+            block.solid = solid;
+            commands.entity(tile).insert(block);
+            if !solid {
+                spawn_tile_gone(commands, tile);
+            }
+        }
+    }
+    tile
+}
+
+//Takes the collider and sprite off a tile that is streamed back in while crumbled or off the beat
+fn spawn_tile_gone(commands: &mut Commands, tile: Entity) {
+    commands
+        .entity(tile)
+        .remove::<Collider>()
+        .insert(Visibility { is_visible: false });
+}
+
+//Puts the drops of broken tiles in their place and remembers the change for when the chunk is streamed back in
+fn replace_broken_tiles(
+    mut commands: Commands,
+    tile_assets: TileAssets,
+    loaded_level: Option<Res<LoadedLevel>>,
+    streamed: Option<ResMut<StreamedChunks>>,
+    mut broken_events: EventReader<TileBroken>
+) {
+    let (loaded_level, mut streamed) = match (loaded_level, streamed) {
+        (Some(loaded_level), Some(streamed)) => (loaded_level, streamed),
+        _ => return,
+    };
+
+    for broken in broken_events.iter() {
+        let (symbol, def) = match broken.drop.and_then(|symbol| loaded_level.legend.get(symbol).map(|def| (symbol, def))) {
+            Some(drop) => drop,
+            None => {
+                streamed.clear_cell(broken.x, broken.y);
+                continue;
+            }
+        };
+        streamed.replace_cell(broken.x, broken.y, symbol);
+
+        let root = match streamed.spawned.get(&chunk_coord(broken.x, broken.y)) {
+            Some(root) => *root,
+            None => continue,
+        };
+        let (tileset, atlas_index) = tile_art(&tile_assets, &loaded_level, def, broken.x, broken.y);
+        let tile = spawn_dynamic_tile(&mut commands, &loaded_level, &mut streamed, def, tileset, atlas_index, broken.x, broken.y);
+        commands.entity(root).add_child(tile);
+    }
+}
+
+//Spawns chunks coming into range of the camera and despawns the ones left far behind
+fn stream_chunks(
+    mut commands: Commands,
+    mut tile_assets: TileAssets,
+    loaded_level: Option<Res<LoadedLevel>>,
+    streamed: Option<ResMut<StreamedChunks>>,
+    camera_query: Query<(&Transform, &OrthographicProjection), With<Camera>>,
+    chunk_tiles: ChunkTiles
+) {
+    let (loaded_level, mut streamed) = match (loaded_level, streamed) {
+        (Some(loaded_level), Some(streamed)) => (loaded_level, streamed),
+        _ => return,
+    };
+    let map = match chunk_tiles.map_query.get_single() {
+        Ok(map) => map,
+        Err(_) => return,
+    };
+    let (camera_transform, projection) = match camera_query.get_single() {
+        Ok(camera) => camera,
+        Err(_) => return,
+    };
+
+    let center = camera_transform.translation.truncate();
+    let view_min = center + Vec2::new(projection.left, projection.bottom);
+    let view_max = center + Vec2::new(projection.right, projection.top);
+    let (width, height) = (loaded_level.level.width, loaded_level.level.height);
+    let wanted = ChunkRange::around(view_min, view_max, STREAM_IN_MARGIN, width, height);
+    let kept = ChunkRange::around(view_min, view_max, STREAM_OUT_MARGIN, width, height);
+    let streamed = &mut *streamed;
+
+    let distant: Vec<((usize, usize), Entity)> = streamed
+        .spawned
+        .iter()
+        .filter(|(coord, _)| !kept.is_some_and(|kept| kept.contains(**coord)))
+        .map(|(coord, root)| (*coord, *root))
+        .collect();
+
+    for (coord, root) in distant {
+        chunk_tiles.save_tile_state(root, streamed);
+        commands.entity(root).despawn_recursive();
+        streamed.spawned.remove(&coord);
+    }
+
+    //Merged colliders stay as long as any chunk they reach into is spawned
+    let spawned = &streamed.spawned;
+    streamed.merged_colliders.retain(|index, collider| {
+        let needed = ChunkRange::covering(loaded_level.merged[*index].1)
+            .coords()
+            .any(|coord| spawned.contains_key(&coord));
+        if !needed {
+            commands.entity(*collider).despawn_recursive();
+        }
+        needed
+    });
+
+    if !tile_assets.tilesets_ready(&loaded_level) {
+        return;
+    }
+    if let Some(wanted) = wanted {
+        for coord in wanted.coords() {
+            if streamed.spawned.contains_key(&coord) {
+                continue;
+            }
+            let root = spawn_chunk(&mut commands, &mut tile_assets, &loaded_level, streamed, map, coord);
+            commands.entity(map).add_child(root);
+            streamed.spawned.insert(coord, root);
+            spawn_merged_colliders(&mut commands, &loaded_level, streamed, map, coord);
+        }
+    }
+}
+
+//Spawns the merged colliders reaching into a chunk that is being spawned, unless a neighbouring chunk already has.
+//They are added to the Map instead of a chunk root, as they often span several chunks
+fn spawn_merged_colliders(
+    commands: &mut Commands,
+    loaded_level: &LoadedLevel,
+    streamed: &mut StreamedChunks,
+    map: Entity,
+    coord: (usize, usize)
+) {
+    for (index, (symbol, rect)) in loaded_level.merged.iter().enumerate() {
+        if streamed.merged_colliders.contains_key(&index) || !ChunkRange::covering(*rect).contains(coord) {
+            continue;
+        }
+        let collider = spawn_merged_collider(commands, &loaded_level.legend.tiles[symbol], *rect);
+        commands.entity(map).add_child(collider);
+        streamed.merged_colliders.insert(index, collider);
+    }
+}
+
+//One collider covering a whole rectangle of identical tiles, so the player
+//doesn't catch on the seams between single tile colliders
+fn spawn_merged_collider(commands: &mut Commands, def: &TileDef, rect: TileRect) -> Entity {
+    let (half_width, half_height) = match def.collider {
+        Some(ColliderShape::Cuboid { half_width, half_height }) => (half_width, half_height),
+        _ => (0.0, 0.0),
+    };
+    let first = tile_translation(rect.x, rect.y);
+    let last = tile_translation(rect.x + rect.width - 1, rect.y + rect.height - 1);
+
+    let merged = TileDef {
+        collider: Some(ColliderShape::Cuboid {
+            half_width: half_width + (last.x - first.x) / 2.0,
+            half_height: half_height + (first.y - last.y) / 2.0,
+        }),
+        ..def.clone()
+    };
+
+    let collider = commands
+        .spawn()
+        .insert(Name::new("Merged collider"))
+        .insert(Transform::from_translation((first + last) / 2.0))
+        .insert(GlobalTransform::default())
+        .id();
+    insert_tile_components(commands, collider, &merged);
+    collider
+}
+
+pub fn tile_translation(x: usize, y: usize) -> Vec3 {
+    Vec3::new(x as f32 * TILE_SIZE, -(y as f32) * TILE_SIZE, 100.0)
+}
+
+//Completes the level when the player touches a goal tile
+fn check_level_goal(
+    current_level: Res<CurrentLevel>,
+    player_query: Query<&Transform, With<Player>>,
+    goal_query: Query<&Transform, (With<LevelGoal>, Without<Player>)>,
+    mut completed_events: EventWriter<LevelCompleted>
+) {
+    let player_translation = match player_query.get_single() {
+        Ok(transform) => transform.translation,
+        Err(_) => return,
+    };
+
+    let reached_goal = goal_query
+        .iter()
+        .any(|transform| wall_collision_check(player_translation, transform.translation));
+
+    if reached_goal {
+        completed_events.send(LevelCompleted { index: current_level.0 });
+    }
+}
+
+//Remembers the last checkpoint the player has touched
+fn activate_checkpoints(
+    mut active_checkpoint: ResMut<ActiveCheckpoint>,
+    player_query: Query<&Transform, With<Player>>,
+    checkpoint_query: Query<&Transform, (With<Checkpoint>, Without<Player>)>
+) {
+    let player_translation = match player_query.get_single() {
+        Ok(transform) => transform.translation,
+        Err(_) => return,
+    };
+
+    let touched = checkpoint_query
+        .iter()
+        .find(|transform| wall_collision_check(player_translation, transform.translation));
+
+    if let Some(transform) = touched {
+        let respawn = transform.translation.truncate().extend(PLAYER_START.z);
+        if active_checkpoint.0 != Some(respawn) {
+            info!("Checkpoint reached");
+            active_checkpoint.0 = Some(respawn);
+        }
+    }
+}
+
+//Works out which channels are on from the levers that are flipped and the pressure plates the player stands on
+fn press_switches(
+    keyboard: Res<Input<KeyCode>>,
+    mut channels: ResMut<SwitchChannels>,
+    player_query: Query<&Transform, With<Player>>,
+    mut switch_query: Query<(&Transform, &Switch, &TileCell, Option<&mut TextureAtlasSprite>), Without<Player>>,
+    mut toggled_events: EventWriter<SwitchToggled>
+) {
+    let player_translation = match player_query.get_single() {
+        Ok(transform) => transform.translation,
+        Err(_) => return,
+    };
+    let mut pressed = HashSet::new();
+
+    for (transform, switch, cell, sprite) in switch_query.iter_mut() {
+        let touching = wall_collision_check(player_translation, transform.translation);
+
+        match switch.kind {
+            SwitchKind::PressurePlate => {
+                if touching {
+                    pressed.insert(switch.channel);
+                }
+            }
+            SwitchKind::Lever => {
+                let cell = (cell.x, cell.y);
+                if touching && keyboard.just_pressed(KeyCode::E) && channels.levers.remove(&cell).is_none() {
+                    channels.levers.insert(cell, switch.channel);
+                }
+                if let Some(mut sprite) = sprite {
+                    sprite.flip_x = channels.levers.contains_key(&cell);
+                }
+            }
+        }
+    }
+    pressed.extend(channels.levers.values().copied());
+
+    if pressed == channels.active {
+        return;
+    }
+    for channel in pressed.symmetric_difference(&channels.active) {
+        toggled_events.send(SwitchToggled { channel: *channel, active: pressed.contains(channel) });
+    }
+    channels.active = pressed;
+}
+
+//Operates the doors and platforms on a channel when it is toggled. Targets that have just been
+//streamed in or rebuilt follow the current state of their channel instead
+fn operate_switch_targets(
+    mut commands: Commands,
+    channels: Res<SwitchChannels>,
+    mut toggled_events: EventReader<SwitchToggled>,
+    mut target_query: Query<SwitchTargetData>
+) {
+    let toggled: HashMap<u32, bool> = toggled_events
+        .iter()
+        .map(|toggled| (toggled.channel, toggled.active))
+        .collect();
+
+    for (entity, target, tracker, door, platform, visibility) in target_query.iter_mut() {
+        let active = match toggled.get(&target.channel) {
+            Some(active) => *active,
+            None if tracker.is_added() => channels.is_active(target.channel),
+            None => continue,
+        };
+
+        if let Some(mut door) = door {
+            if door.open != active {
+                door.open = active;
+                set_tile_solid(&mut commands, entity, &door.shape, !active, visibility);
+            }
+        }
+        if let Some(mut platform) = platform {
+            platform.powered = active;
+        }
+    }
+}
+
+//Crumbles tiles the player stands on, and brings them back once their respawn time is up
+fn crumble_tiles(
+    mut commands: Commands,
     time: Res<Time>,
-    mut transform_query: Query<&mut Transform, With<MovingCollider>>,
-    mut query_entity: Query<Entity, With<MovingCollider>>,
-    mut query_moving_collider: Query<&mut MovingCollider>,
-    mut velocities: Query<&mut Velocity, With<MovingCollider>>
+    rapier_context: Res<RapierContext>,
+    player_query: Query<(Entity, &Transform), With<Player>>,
+    mut crumbling_query: Query<CrumblingTileData, Without<Player>>
 ) {
-    let speed = 0.3;
-
-    for mut moving_collider in query_moving_collider.iter_mut() {
-        moving_collider.timer.tick(time.delta());
-
-        if moving_collider.timer.finished() {
-            let mut velocity = 0.0;
-            let mut right = false;
-            
-            if moving_collider.movements_right < 3 {
-                right = true;
-                moving_collider.movements_right += 1;
-                velocity = speed;
-            } else if moving_collider.movements_left < 3 {
-                right = false;
-                moving_collider.movements_left += 1;
-                velocity = -speed;
-                
-                if moving_collider.movements_left == 3 {
-                    moving_collider.movements_left = 0;
-                    moving_collider.movements_right = 0;
+    let (player, player_transform) = match player_query.get_single() {
+        Ok(player) => player,
+        Err(_) => return,
+    };
+
+    for (entity, mut crumbling, transform, visibility, sprite) in crumbling_query.iter_mut() {
+        match crumbling.state {
+            CrumbleState::Intact => {
+                if standing_on(&rapier_context, player, player_transform.translation, entity, transform.translation) {
+                    crumbling.state = CrumbleState::Crumbling;
+                    crumbling.timer = Timer::from_seconds(crumbling.timing.delay, false);
                 }
             }
+            CrumbleState::Crumbling => {
+                crumbling.timer.tick(time.delta());
+                if let Some(mut sprite) = sprite {
+                    sprite.color.set_a(1.0 - crumbling.timer.percent() * (1.0 - CRUMBLE_MIN_ALPHA));
+                }
 
-            for mut transform in transform_query.iter_mut() {
-                if right {
-                    transform.translation.x = transform.translation.x + speed;
-                } else {
-                    transform.translation.x = transform.translation.x - speed;
+                if crumbling.timer.finished() {
+                    crumbling.state = CrumbleState::Gone;
+                    crumbling.timer = Timer::from_seconds(crumbling.timing.respawn, false);
+                    set_tile_solid(&mut commands, entity, &crumbling.shape, false, visibility);
                 }
             }
+            CrumbleState::Gone => {
+                crumbling.timer.tick(time.delta());
 
-            for mut vel in velocities.iter_mut() {
-                vel.linvel = Vec2::new(velocity, 10.0);
+                //Never comes back inside the player
+                let blocked = wall_collision_check(player_transform.translation, transform.translation);
+
+                if crumbling.timer.finished() && !blocked {
+                    crumbling.state = CrumbleState::Intact;
+                    if let Some(mut sprite) = sprite {
+                        sprite.color.set_a(1.0);
+                    }
+                    set_tile_solid(&mut commands, entity, &crumbling.shape, true, visibility);
+                }
             }
         }
     }
 }
 
-//Unused
-fn show_map(
-    children_query: Query<&Children, With<Map>>,
-    mut child_visibility_query: Query<&mut Visibility, Without<Map>>, 
+//Advances the beat and makes Timed tiles follow it, including ones that have just been streamed in
+fn toggle_timed_tiles(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut clock: ResMut<BeatClock>,
+    player_query: Query<&Transform, With<Player>>,
+    mut timed_query: Query<(Entity, &mut TimedBlock, &Transform, Option<&mut Visibility>), Without<Player>>
 ) {
-    if let Ok(children) = children_query.get_single() {
-        for child in children.iter() {
-            if let Ok(mut child_vis) = child_visibility_query.get_mut(*child) {
-                child_vis.is_visible = true;
+    clock.timer.tick(time.delta());
+    if clock.timer.just_finished() {
+        clock.beat = clock.beat.wrapping_add(clock.timer.times_finished());
+    }
+    let player_translation = player_query.get_single().ok().map(|transform| transform.translation);
+
+    for (entity, mut block, transform, visibility) in timed_query.iter_mut() {
+        let solid = block.pattern.is_solid(clock.beat);
+        if solid == block.solid {
+            continue;
+        }
+        //Never appears inside the player, it waits until the player has moved out
+        if solid && player_translation.is_some_and(|player| wall_collision_check(player, transform.translation)) {
+            continue;
+        }
+        block.solid = solid;
+        set_tile_solid(&mut commands, entity, &block.shape, solid, visibility);
+    }
+}
+
+//Adds or removes a tile's collider together with its sprite. The tile keeps its other components,
+//so ground checks against TileCollider simply stop finding contacts while it is gone
+fn set_tile_solid(
+    commands: &mut Commands,
+    entity: Entity,
+    shape: &Option<ColliderShape>,
+    solid: bool,
+    visibility: Option<Mut<Visibility>>
+) {
+    if !solid {
+        commands.entity(entity).remove::<Collider>();
+    } else if let Some(shape) = shape {
+        commands.entity(entity).insert(shape.to_collider());
+    }
+    if let Some(mut visibility) = visibility {
+        visibility.is_visible = solid;
+    }
+}
+
+//Requests the next level in the registry, the current Map is replaced once it has loaded
+fn advance_level(
+    asset_server: Res<AssetServer>,
+    registries: Res<Assets<LevelRegistry>>,
+    mut handles: ResMut<LevelHandles>,
+    mut current_level: ResMut<CurrentLevel>,
+    mut completed_events: EventReader<LevelCompleted>
+) {
+    let completed = match completed_events.iter().last() {
+        Some(completed) => completed.index,
+        None => return,
+    };
+    //The goal keeps being touched until the next level replaces it
+    if handles.pending {
+        return;
+    }
+    let registry = match registries.get(&handles.registry) {
+        Some(registry) => registry,
+        None => return,
+    };
+
+    let mut next = completed + 1;
+    if next >= registry.levels.len() {
+        info!("Campaign complete");
+        next = 0;
+    }
+    current_level.0 = next;
+
+    request_level(&asset_server, registry, next, &mut handles);
+}
+
+//Logs levels whose files could not be loaded
+fn report_failed_levels(mut failed_events: EventReader<LevelLoadFailed>) {
+    for failed in failed_events.iter() {
+        error!("Could not build stage {} from {}", failed.index + 1, failed.path);
+    }
+}
+
+impl<'a> PhysicsHooksWithQuery<TileHookData<'a>> for TileContactHooks {
+    fn modify_solver_contacts(&self, context: ContactModificationContextView, user_data: &Query<TileHookData<'a>>) {
+        let one_way = |entity| matches!(user_data.get(entity), Ok((Some(_), _, _)));
+        let dropping = |entity| matches!(user_data.get(entity), Ok((_, Some(_), _)));
+        let conveyor = |entity| match user_data.get(entity) {
+            Ok((_, _, Some(conveyor))) => Some(conveyor.speed),
+            _ => None,
+        };
+        let (collider1, collider2) = (context.collider1(), context.collider2());
+
+        //Surface velocity is relative to the first collider, hooks work in meters
+        let surface_speed = match (conveyor(collider1), conveyor(collider2)) {
+            (Some(speed), _) => Some(speed),
+            (None, Some(speed)) => Some(-speed),
+            (None, None) => None,
+        };
+        if let Some(speed) = surface_speed {
+            for solver_contact in context.raw.solver_contacts.iter_mut() {
+                solver_contact.tangent_velocity = Vector::x() * speed / PIXELS_PER_METER;
             }
         }
+
+        //The allowed normal is in the local space of the first collider, pointing away from it
+        let (allowed_local_n1, other) = if one_way(collider1) {
+            (Vector::y(), collider2)
+        } else if one_way(collider2) {
+            (-Vector::y(), collider1)
+        } else {
+            return;
+        };
+
+        if dropping(other) {
+            context.raw.solver_contacts.clear();
+        } else {
+            context.raw.update_as_oneway_platform(&allowed_local_n1, 0.1);
+        }
+    }
+}
+
+//Moves every platform along its own path. Kinematic bodies follow their Transform, so the motion stays smooth
+fn move_floating_platform(
+    time: Res<Time>,
+    mut platform_query: Query<(&mut MovingCollider, &mut Transform)>
+) {
+    for (mut platform, mut transform) in platform_query.iter_mut() {
+        let origin = *platform.origin.get_or_insert(transform.translation);
+
+        if !platform.powered {
+            platform.displacement = Vec3::ZERO;
+            continue;
+        }
+
+        platform.displacement = match platform.advance(origin, time.delta_seconds()) {
+            Some(translation) => {
+                let displacement = translation - transform.translation;
+                transform.translation = translation;
+                displacement
+            }
+            None => Vec3::ZERO,
+        };
+    }
+}
+
+//Shows again what hide_map hid, leaving open doors, crumbled tiles and culled chunks hidden
+fn show_map(mut commands: Commands, mut hidden_query: Query<(Entity, &mut Visibility), With<HiddenWithMap>>) {
+    for (entity, mut visibility) in hidden_query.iter_mut() {
+        visibility.is_visible = true;
+        commands.entity(entity).remove::<HiddenWithMap>();
     }
 }
 
+//Visibility is not inherited from the Map or chunk roots, so everything below them that is drawn gets
+//hidden and tagged for show_map
 fn hide_map(
-    children_query: Query<&Children, With<Map>>,
-    mut child_visibility_query: Query<&mut Visibility, Without<Map>>, 
+    mut commands: Commands,
+    map_query: Query<Entity, With<Map>>,
+    children_query: Query<&Children>,
+    mut visibility_query: Query<&mut Visibility>
 ) {
-    if let Ok(children) = children_query.get_single() {
-        for child in children.iter() {
-            if let Ok(mut child_vis) = child_visibility_query.get_mut(*child) {
-                child_vis.is_visible = false;
+    let mut entities: Vec<Entity> = map_query.iter().collect();
+    while let Some(entity) = entities.pop() {
+        if let Ok(mut visibility) = visibility_query.get_mut(entity) {
+            if visibility.is_visible {
+                visibility.is_visible = false;
+                commands.entity(entity).insert(HiddenWithMap);
             }
         }
+        if let Ok(children) = children_query.get(entity) {
+            entities.extend(children.iter().copied());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::legend::Easing;
+
+    //Two waypoints one cell apart, a second per cell
+    fn platform(mode: PathMode) -> MovingCollider {
+        MovingCollider::new(PlatformPath {
+            waypoints: vec![(1, 0), (2, 0)],
+            speed: 1.0,
+            easing: Easing::Linear,
+            mode,
+            pause: 0.0,
+            delay: 0.0,
+        })
+    }
+
+    //Cells from the origin after each of steps seconds, None once the platform has stopped
+    fn cells(platform: &mut MovingCollider, steps: usize, delta: f32) -> Vec<Option<f32>> {
+        (0..steps)
+            .map(|_| platform.advance(Vec3::ZERO, delta).map(|at| (at.x / TILE_SIZE * 10.0).round() / 10.0))
+            .collect()
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn ping_pong_turns_at_both_ends() {
+        let mut platform = platform(PathMode::PingPong);
+
+        assert_eq!(cells(&mut platform, 1, 0.5), vec![Some(0.5)]);
+        assert_eq!(
+            cells(&mut platform, 5, 1.01),
+            vec![Some(1.0), Some(2.0), Some(1.0), Some(0.0), Some(1.0)]
+        );
+    }
+
+    #[test]
+    fn loop_returns_to_the_start() {
+        let mut platform = platform(PathMode::Loop);
+
+        assert_eq!(cells(&mut platform, 2, 1.01), vec![Some(1.0), Some(2.0)]);
+        //Straight back in one segment two cells long, where PingPong would stop at the middle waypoint
+        assert_eq!(cells(&mut platform, 1, 2.02), vec![Some(0.0)]);
+        assert_eq!(cells(&mut platform, 1, 1.01), vec![Some(1.0)]);
+    }
+
+    #[test]
+    fn once_stops_at_the_last_waypoint() {
+        let mut platform = platform(PathMode::Once);
+
+        assert_eq!(cells(&mut platform, 4, 1.01), vec![Some(1.0), Some(2.0), None, None]);
+    }
+}
@@ -0,0 +1,149 @@
+use std::collections::HashMap;
+
+use bevy::{
+    prelude::*,
+    asset::{AssetLoader, AssetPath, BoxedFuture, HandleId, LoadContext, LoadedAsset},
+    reflect::TypeUuid,
+};
+use serde::Deserialize;
+
+use crate::autotile::AutotileRules;
+
+//Tileset used by legends that do not name one, relative to the assets folder
+pub const DEFAULT_TILESET: &str = "ground.tileset.ron";
+
+//Label of the TextureAtlas every tileset file adds next to the Tileset itself
+const ATLAS_LABEL: &str = "atlas";
+
+pub struct TilesetPlugin;
+
+//Contents of a .tileset.ron file. The image path is relative to the file
+#[derive(Debug, Clone, Deserialize)]
+pub struct TilesetDescriptor {
+    pub image: String,
+    pub cell_size: (f32, f32),
+    #[serde(default)]
+    pub padding: (f32, f32),
+    pub columns: usize,
+    pub rows: usize,
+    //Atlas indices legends can refer to by name
+    #[serde(default)]
+    pub tiles: HashMap<String, usize>,
+    //Autotile rule sets legends can refer to by name
+    #[serde(default)]
+    pub autotile: HashMap<String, AutotileRules>,
+}
+
+#[derive(TypeUuid)]
+#[uuid = "9d3e61a4-7f25-4b8c-b0e2-5a6c1f0d8e37"]
+pub struct Tileset {
+    pub atlas: Handle<TextureAtlas>,
+    pub texture: Handle<Image>,
+    pub tiles: HashMap<String, usize>,
+    pub autotile: HashMap<String, AutotileRules>,
+}
+
+#[derive(Default)]
+pub struct TilesetLoader;
+
+//Every tileset that has been requested, keyed by path, and the material chunk meshes draw it with
+#[derive(Default)]
+pub struct Tilesets {
+    handles: HashMap<String, Handle<Tileset>>,
+    materials: HashMap<HandleId, Handle<ColorMaterial>>,
+}
+
+impl Tileset {
+    pub fn index(&self, name: &str) -> Option<usize> {
+        self.tiles.get(name).copied()
+    }
+
+    pub fn autotile(&self, name: &str) -> Option<&AutotileRules> {
+        self.autotile.get(name)
+    }
+}
+
+impl Tilesets {
+    //Starts loading the tileset unless it already has been, tilesets stay loaded side by side from then on
+    pub fn request(&mut self, asset_server: &AssetServer, path: &str) -> Handle<Tileset> {
+        self.handles
+            .entry(path.to_string())
+            .or_insert_with(|| asset_server.load(path))
+            .clone()
+    }
+
+    pub fn material(&self, handle: &Handle<Tileset>) -> Option<&Handle<ColorMaterial>> {
+        self.materials.get(&handle.id)
+    }
+}
+
+impl Plugin for TilesetPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_asset::<Tileset>()
+        .init_asset_loader::<TilesetLoader>()
+        .init_resource::<Tilesets>()
+        .add_system(create_tileset_materials);
+    }
+}
+
+impl AssetLoader for TilesetLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext
+    ) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            let descriptor: TilesetDescriptor = ron::de::from_bytes(bytes)?;
+            let directory = load_context.path().parent().map(|path| path.to_path_buf()).unwrap_or_default();
+            let image_path = AssetPath::new(directory.join(&descriptor.image), None);
+            let texture: Handle<Image> = load_context.get_handle(image_path.clone());
+
+            let atlas = load_context.set_labeled_asset(ATLAS_LABEL, LoadedAsset::new(TextureAtlas::from_grid_with_padding(
+                texture.clone(),
+                Vec2::new(descriptor.cell_size.0, descriptor.cell_size.1),
+                descriptor.columns,
+                descriptor.rows,
+                Vec2::new(descriptor.padding.0, descriptor.padding.1)
+            )));
+
+            load_context.set_default_asset(
+                LoadedAsset::new(Tileset {
+                    atlas,
+                    texture,
+                    tiles: descriptor.tiles,
+                    autotile: descriptor.autotile,
+                }).with_dependency(image_path)
+            );
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["tileset.ron"]
+    }
+}
+
+//Gives every tileset a material as soon as it has loaded, and a new one when its file changes
+fn create_tileset_materials(
+    mut tileset_events: EventReader<AssetEvent<Tileset>>,
+    tileset_assets: Res<Assets<Tileset>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    mut tilesets: ResMut<Tilesets>
+) {
+    for event in tileset_events.iter() {
+        match event {
+            AssetEvent::Created { handle } | AssetEvent::Modified { handle } => {
+                if let Some(tileset) = tileset_assets.get(handle) {
+                    let material = materials.add(ColorMaterial {
+                        color: Color::rgb(1.0, 1.0, 1.0),
+                        texture: Some(tileset.texture.clone()),
+                    });
+                    tilesets.materials.insert(handle.id, material);
+                }
+            }
+            AssetEvent::Removed { handle } => {
+                tilesets.materials.remove(&handle.id);
+            }
+        }
+    }
+}